@@ -1,4 +1,7 @@
 fn main() {
-    prost_build::compile_protos(&["proto/order.proto"], &["proto"])
+    // Messages also derive Serialize so market data can be published as JSON for debugging
+    prost_build::Config::new()
+        .type_attribute(".", "#[derive(serde::Serialize)]")
+        .compile_protos(&["proto/order.proto"], &["proto"])
         .unwrap_or_else(|e| panic!("Failed to compile protos {:?}", e));
-} 
\ No newline at end of file
+}
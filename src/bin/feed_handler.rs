@@ -1,38 +1,73 @@
-use async_nats;
 use rand::seq::SliceRandom;
 use rand::Rng;
+use rust_validator::instrument::InstrumentRegistry;
 use rust_validator::utils::now_nanos;
 use tokio::time::{sleep, Duration};
+use rust_validator::messaging::proto;
 use prost::Message;
 
-mod proto {
-    include!(concat!(env!("OUT_DIR"), "/order.rs"));
-}
-
 #[tokio::main]
 async fn main() {
     let nc = async_nats::connect("localhost:4222").await.expect("Failed to connect to NATS");
     let actions = [proto::Action::Buy, proto::Action::Sell];
-    let order_types = [proto::OrderType::Market, proto::OrderType::Limit, proto::OrderType::Cancel];
+    let order_types = [
+        proto::OrderType::Market,
+        proto::OrderType::Limit,
+        proto::OrderType::Cancel,
+        proto::OrderType::Replace,
+    ];
     let mut rng = rand::thread_rng();
-    let symbol = "TSLA";
+    let registry = InstrumentRegistry::from_env().expect("Failed to load instrument registry");
+    let symbol = std::env::var("SYMBOL").unwrap_or_else(|_| "TSLA".to_string());
+    let instrument = registry.get(&symbol).expect("Symbol is not in the instrument registry");
+    // Prices are drawn within 25% either side of the reference price
+    let reference_price = instrument.reference_price.unwrap_or(300.0);
+    let price_range = reference_price * 0.75..reference_price * 1.25;
+    // Ids of limit orders sent so far, so cancels and replaces can target a real order
+    let mut live_ids: Vec<u64> = Vec::new();
 
     loop {
-        let action = actions.choose(&mut rng).unwrap().clone();
-        let order_type = order_types.choose(&mut rng).unwrap().clone();
-        let price = ((rng.gen_range(100.0f64..500.0f64) * 10.0).round()) / 10.0;
+        let action = *actions.choose(&mut rng).unwrap();
+        let mut order_type = *order_types.choose(&mut rng).unwrap();
+        let targets_live_order = matches!(order_type, proto::OrderType::Cancel | proto::OrderType::Replace);
+        if targets_live_order && live_ids.is_empty() {
+            order_type = proto::OrderType::Limit;
+        }
+        let price = instrument
+            .round_to_tick(rng.gen_range(price_range.clone()))
+            .expect("simulated price is finite")
+            .to_f64();
         let amount = rng.gen_range(1.0..100.0) as i32;
 
         let mut buf = Vec::with_capacity(64);
         let now_ns = now_nanos();
+        let id = match order_type {
+            proto::OrderType::Cancel => live_ids.swap_remove(rng.gen_range(0..live_ids.len())),
+            proto::OrderType::Replace => live_ids[rng.gen_range(0..live_ids.len())],
+            proto::OrderType::Limit => {
+                live_ids.push(now_ns as u64);
+                now_ns as u64
+            }
+            _ => now_ns as u64,
+        };
+        let (price, amount) = if order_type == proto::OrderType::Cancel {
+            (0.0, 0) // Cancel the whole resting order
+        } else {
+            (price, amount)
+        };
         let order = proto::Order {
-            id: now_ns as u64,
+            id,
             price,
             amount,
             action: action as i32,
             order_type: order_type as i32,
             timestamp: now_ns as u64,
-            instrument: symbol.to_string(),
+            instrument: symbol.clone(),
+            time_in_force: proto::TimeInForce::Gtc as i32,
+            display_amount: 0,
+            stop_price: 0.0,
+            owner: String::new(),
+            post_only: false,
         };
         order.encode(&mut buf).unwrap();
         nc.publish("market_data".into(), buf.into()).await.unwrap();
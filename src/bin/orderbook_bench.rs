@@ -0,0 +1,108 @@
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rust_validator::orderbook::{Action, Order, OrderBook, OrderType, TimeInForce};
+use rust_validator::price::Price;
+use std::hint::black_box;
+use std::time::Instant;
+
+const OPS: usize = 200_000;
+const DEPTHS: [i64; 4] = [100, 1_000, 10_000, 100_000];
+// Bids sit below MID, asks above it, one tick apart
+const MID: i64 = 1_000_000;
+const TICK: Price = Price::from_units(10_000);
+
+fn order(id: u128, ticks: i64, amount: i32, action: Action) -> Order {
+    Order {
+        id,
+        price: Price::from_units(ticks * TICK.units()),
+        amount,
+        action,
+        order_type: OrderType::Limit,
+        timestamp: id,
+        instrument: "BENCH".to_string(),
+        time_in_force: TimeInForce::Gtc,
+        display_amount: 0,
+        hidden_amount: 0,
+        stop_price: Price::ZERO,
+        owner: String::new(),
+        post_only: false,
+    }
+}
+
+/// Builds a book with `depth` levels on each side
+fn seeded_book(depth: i64) -> OrderBook {
+    let mut book = OrderBook::with_tick_size("BENCH".to_string(), TICK);
+    for i in 0..depth {
+        book.add_order(&order(2 * i as u128, MID - 1 - i, 100, Action::Buy)).expect("seed order");
+        book.add_order(&order(2 * i as u128 + 1, MID + 1 + i, 100, Action::Sell)).expect("seed order");
+    }
+    book
+}
+
+fn main() {
+    let mut rng = StdRng::seed_from_u64(42);
+    println!(
+        "{:>8} | {:>14} | {:>14} | {:>14}",
+        "levels", "passive ns/op", "crossing ns/op", "cancel ns/op"
+    );
+    for depth in DEPTHS {
+        let mut book = seeded_book(depth);
+        let mut next_id = 10 * depth as u128;
+
+        // Passive orders land on a random existing level without crossing
+        let passive: Vec<Order> = (0..OPS)
+            .map(|i| {
+                next_id += 1;
+                let offset = rng.gen_range(0..depth);
+                if i % 2 == 0 {
+                    order(next_id, MID - 1 - offset, 10, Action::Buy)
+                } else {
+                    order(next_id, MID + 1 + offset, 10, Action::Sell)
+                }
+            })
+            .collect();
+        let start = Instant::now();
+        for o in &passive {
+            let _ = black_box(book.add_order(o));
+        }
+        let passive_ns = start.elapsed().as_nanos() / OPS as u128;
+
+        // Crossing orders take a slice of the touch, alternating sides
+        let crossing: Vec<Order> = (0..OPS)
+            .map(|i| {
+                next_id += 1;
+                if i % 2 == 0 {
+                    order(next_id, MID + depth, 5, Action::Buy)
+                } else {
+                    order(next_id, MID - depth, 5, Action::Sell)
+                }
+            })
+            .collect();
+        let start = Instant::now();
+        for o in &crossing {
+            let _ = black_box(book.add_order(o));
+        }
+        let crossing_ns = start.elapsed().as_nanos() / OPS as u128;
+
+        // Cancel the passive orders that are still resting, in random order.
+        // Each cancel scans the queue at its price, so this grows with the
+        // number of orders per level rather than with the number of levels.
+        let mut ids: Vec<u128> = passive
+            .iter()
+            .filter(|o| book.get_order(o.id).is_some())
+            .map(|o| o.id)
+            .collect();
+        ids.shuffle(&mut rng);
+        let start = Instant::now();
+        for &id in &ids {
+            black_box(book.cancel_order(id, 0));
+        }
+        let cancel_ns = start.elapsed().as_nanos() / ids.len().max(1) as u128;
+
+        println!(
+            "{:>8} | {:>14} | {:>14} | {:>14}",
+            depth, passive_ns, crossing_ns, cancel_ns
+        );
+    }
+}
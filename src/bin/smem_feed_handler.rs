@@ -4,25 +4,31 @@ use rand::rngs::ThreadRng;
 use rand::seq::SliceRandom;
 use rand::Rng;
 use rand_distr::Distribution;
+use rust_validator::instrument::{Instrument, InstrumentRegistry};
 use rust_validator::utils::now_nanos;
 use std::fs::OpenOptions;
 use std::{thread, time::Duration};
 use prost::Message;
-
-mod proto {
-    include!(concat!(env!("OUT_DIR"), "/order.rs"));
-}
+use rust_validator::messaging::proto;
 
 const SHM_SIZE: usize = 4096; // Adjust as needed
+// Per-order mid price drift, as a fraction of the reference price
+const RELATIVE_VOLATILITY: f64 = 0.0007;
 
 struct InstrumentSimulator {
+    instrument: Instrument,
     mid_price: f64,
     volatility: f64,
 }
 
 impl InstrumentSimulator {
-    fn new(mid_price: f64, volatility: f64) -> Self {
-        Self { mid_price, volatility }
+    fn new(instrument: Instrument) -> Self {
+        let mid_price = instrument.reference_price.unwrap_or(100.0);
+        Self {
+            instrument,
+            mid_price,
+            volatility: mid_price * RELATIVE_VOLATILITY,
+        }
     }
 
     fn next_order_price(&mut self, is_buy: bool, rng: &mut ThreadRng) -> f64 {
@@ -35,11 +41,11 @@ impl InstrumentSimulator {
         } else {
             rand_distr::Normal::new(self.mid_price + 0.5, 1.0).unwrap()
         };
-        let mut price = normal.sample(rng);
-        if price < 1.0 { price = 1.0; }
-        // Round to nearest 10 cents
-        price = (price * 10.0).round() / 10.0;
-        price
+        let price = normal.sample(rng).max(1.0);
+        self.instrument
+            .round_to_tick(price)
+            .expect("simulated price is finite")
+            .to_f64()
     }
 }
 
@@ -47,31 +53,32 @@ fn main() -> std::io::Result<()> {
     // Get current date in YYYYMMDD format
     let date_str = Local::now().format("%Y%m%d").to_string();
     let exchange = "NYSE";
-    let instrument = "TSLA";
     let shm_path = format!("/tmp/{}.{}", date_str, exchange);
     // Create or open the shared memory file
     let file = OpenOptions::new()
         .read(true)
         .write(true)
         .create(true)
+        .truncate(true)
         .open(&shm_path)?;
     file.set_len(SHM_SIZE as u64)?;
 
     // Memory-map the file
     let mut mmap = unsafe { MmapOptions::new().map_mut(&file)? };
 
-    let instruments = ["TSLA", "AAPL"];
-    // Create a simulator for each instrument with different mean prices
-    let mut simulators = std::collections::HashMap::new();
-    simulators.insert("TSLA", InstrumentSimulator::new(300.0, 0.2));
-    simulators.insert("AAPL", InstrumentSimulator::new(180.0, 0.15));
+    // One simulator per registry instrument, starting from its reference price
+    let registry = InstrumentRegistry::from_env().map_err(std::io::Error::other)?;
+    let mut simulators: Vec<InstrumentSimulator> = registry.iter().cloned().map(InstrumentSimulator::new).collect();
+    if simulators.is_empty() {
+        println!("No instruments in the registry");
+        return Ok(());
+    }
     let actions = [proto::Action::Buy, proto::Action::Sell];
     let mut rng = rand::thread_rng();
 
     loop {
         // Randomly pick an instrument for this order
-        let instrument = instruments.choose(&mut rng).unwrap();
-        let simulator = simulators.get_mut(instrument).unwrap();
+        let simulator = simulators.choose_mut(&mut rng).unwrap();
         // Set to 50% chance for cancel orders for testing
         let order_type = if rng.gen_bool(0.5) {
             proto::OrderType::Limit
@@ -93,7 +100,12 @@ fn main() -> std::io::Result<()> {
             action: action as i32,
             order_type: order_type as i32,
             timestamp: now_ns as u64,
-            instrument: instrument.to_string(),
+            instrument: simulator.instrument.symbol.clone(),
+            time_in_force: proto::TimeInForce::Gtc as i32,
+            display_amount: 0,
+            stop_price: 0.0,
+            owner: String::new(),
+            post_only: false,
         };
         let mut buf = Vec::with_capacity(128);
         order.encode(&mut buf).unwrap();
@@ -113,8 +125,8 @@ fn main() -> std::io::Result<()> {
         if order_type == proto::OrderType::Limit || order_type == proto::OrderType::Cancel {
             let side = if is_buy { "Buy" } else { "Sell" };
             println!(
-                "{}: {:>3} \tLMT # {}: {:<4} {:>3} @ {:.1}",
-                instrument, order_type_str, order.id, side, amount, price
+                "{}: {:>3} \tLMT # {}: {:<4} {:>3} @ {:.*}",
+                order.instrument, order_type_str, order.id, side, amount, simulator.instrument.price_decimals, price
             );
         }
 
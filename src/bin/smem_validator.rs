@@ -6,16 +6,15 @@ use std::thread;
 use std::time::Duration;
 use rust_validator::utils::now_nanos;
 use rust_validator::orderbook::{Order, Action, OrderType};
-use glob;
-
-mod proto {
-    include!(concat!(env!("OUT_DIR"), "/order.rs"));
-}
+use rust_validator::messaging::proto;
+use rust_validator::instrument::InstrumentRegistry;
+use rust_validator::validation::{ValidationConfig, Validator};
 
 const SHM_SIZE: usize = 4096;
 
 fn main() -> std::io::Result<()> {
     let busy_mode = std::env::var("BUSY_MODE").unwrap_or_else(|_| "0".to_string()) == "1";
+    let registry = InstrumentRegistry::from_env().map_err(std::io::Error::other)?;
     // Get current date in YYYYMMDD format
     let date_str = Local::now().format("%Y%m%d").to_string();
     // Find all shared memory files for this date
@@ -28,8 +27,8 @@ fn main() -> std::io::Result<()> {
         return Ok(());
     }
     println!("Found shared memory files: {:?}", files);
-    // For each file, extract the symbol from the filename and process orders
-    for file_path in files {
+    // Only the first file is tailed; extract the exchange from its filename and process orders
+    if let Some(file_path) = files.into_iter().next() {
         let exchange = file_path.file_name().unwrap().to_str().unwrap().split('.').nth(1).unwrap_or("");
         let file = OpenOptions::new()
             .read(true)
@@ -37,6 +36,13 @@ fn main() -> std::io::Result<()> {
             .open(&file_path)?;
         let mut mmap = unsafe { MmapOptions::new().map_mut(&file)? };
         let mut last_id = 0u64;
+        let mut validator = Validator::new(ValidationConfig {
+            lot_size: Some(1),
+            min_quantity: Some(1),
+            max_clock_skew: Some(Duration::from_secs(1)),
+            duplicate_id_window: Some(100_000),
+            ..ValidationConfig::default()
+        });
         // Collect command-line arguments for instrument filtering
         let args: Vec<String> = std::env::args().skip(1).collect();
         let filter_instruments: Option<std::collections::HashSet<String>> = if args.is_empty() {
@@ -54,23 +60,9 @@ fn main() -> std::io::Result<()> {
                     // Only print if it's a new order
                     if proto_order.id != last_id {
                         // No instrument filter: print all orders for now
-                        let order = Order {
-                            id: proto_order.id as u128,
-                            price: proto_order.price,
-                            amount: proto_order.amount,
-                            action: match proto_order.action {
-                                0 => Action::Buy,
-                                1 => Action::Sell,
-                                _ => continue,
-                            },
-                            order_type: match proto_order.order_type {
-                                0 => OrderType::Market,
-                                1 => OrderType::Limit,
-                                2 => OrderType::Cancel,
-                                _ => continue,
-                            },
-                            timestamp: proto_order.timestamp as u128,
-                            instrument: proto_order.instrument.clone(),
+                        let order = match Order::try_from(&proto_order) {
+                            Ok(order) => order,
+                            Err(_) => continue,
                         };
                         // Filter by instrument if a filter is set
                         if let Some(ref filter) = filter_instruments {
@@ -82,11 +74,22 @@ fn main() -> std::io::Result<()> {
                             }
                         }
                         let now = now_nanos();
+                        let instrument = registry.get(&order.instrument);
+                        if let Err(rule) = validator.validate(&order, instrument, None, now) {
+                            println!("{} {}: order # {} failed {} check ({} so far)", exchange, order.instrument, order.id, rule, validator.violations()[&rule]);
+                            last_id = proto_order.id;
+                            let len_ptr = &mut mmap[0..4];
+                            len_ptr.copy_from_slice(&0u32.to_le_bytes());
+                            continue;
+                        }
+                        let decimals = instrument.map_or(1, |i| i.price_decimals);
                         let latency_us = (now as i64 - order.timestamp as i64) / 1000;
                         // Change the log output to match the feed_handler format
                         let order_type_str = match order.order_type {
                             OrderType::Limit => "NEW",
                             OrderType::Cancel => "CNL",
+                            OrderType::Replace => "RPL",
+                            OrderType::Stop | OrderType::StopLimit => "NEW",
                             OrderType::Market => "MKT",
                         };
                         let side = match order.action {
@@ -96,18 +99,21 @@ fn main() -> std::io::Result<()> {
                         let lmt_str = match order.order_type {
                             OrderType::Limit => "LMT",
                             OrderType::Cancel => "LMT",
+                            OrderType::Replace => "LMT",
+                            OrderType::Stop => "STP",
+                            OrderType::StopLimit => "STL",
                             OrderType::Market => "MKT",
                         };
-                        if order.order_type == OrderType::Limit || order.order_type == OrderType::Cancel {
+                        if order.order_type != OrderType::Market {
                             println!(
-                                "{} {}: {:>3} \t{} # {}: {:<4} {:>3} @ {:.1} | Latency: {} us",
-                                exchange, order.instrument, order_type_str, lmt_str, order.id, side, order.amount, order.price, latency_us
+                                "{} {}: {:>3} \t{} # {}: {:<4} {:>3} @ {:.*} | Latency: {} us",
+                                exchange, order.instrument, order_type_str, lmt_str, order.id, side, order.amount, decimals, order.price, latency_us
                             );
                         } else {
                             // For Market or other types, print with Incoming and timestamp
                             println!(
-                                "Incoming {} {} {}: {:>3} \t{} # {}: {:<4} {:>3} @ {:.1} | Latency: {} us",
-                                order.timestamp, exchange, order.instrument, order_type_str, lmt_str, order.id, side, order.amount, order.price, latency_us
+                                "Incoming {} {} {}: {:>3} \t{} # {}: {:<4} {:>3} @ {:.*} | Latency: {} us",
+                                order.timestamp, exchange, order.instrument, order_type_str, lmt_str, order.id, side, order.amount, decimals, order.price, latency_us
                             );
                         }
                         last_id = proto_order.id;
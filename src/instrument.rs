@@ -0,0 +1,267 @@
+use crate::orderbook::{PriceCollar, VolatilityGuard};
+use crate::price::Price;
+use chrono::NaiveTime;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+/// Registry file read when `INSTRUMENTS` is not set
+pub const DEFAULT_REGISTRY_PATH: &str = "instruments.json";
+
+/// Reference data for one tradable symbol
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Instrument {
+    pub symbol: String,
+    /// Minimum price increment, written as a decimal such as `0.01`
+    #[serde(with = "decimal_price")]
+    pub tick_size: Price,
+    /// Quantities must be whole multiples of this
+    #[serde(default = "default_lot_size")]
+    pub lot_size: i32,
+    /// Decimal places used when displaying prices
+    #[serde(default = "default_price_decimals")]
+    pub price_decimals: usize,
+    /// Continuous trading session in UTC; `None` trades around the clock
+    #[serde(default)]
+    pub trading_hours: Option<TradingHours>,
+    /// Starting mid price for simulators
+    #[serde(default)]
+    pub reference_price: Option<f64>,
+    /// Static band of accepted limit prices, as decimal `min` and `max`
+    #[serde(default, with = "decimal_collar")]
+    pub price_collar: Option<PriceCollar>,
+    /// See `OrderBook::dynamic_collar_bps`
+    #[serde(default)]
+    pub dynamic_collar_bps: Option<i64>,
+    /// See `OrderBook::volatility_guard`
+    #[serde(default)]
+    pub volatility_guard: Option<VolatilityGuard>,
+}
+
+/// Daily session window, `open` inclusive and `close` exclusive
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct TradingHours {
+    pub open: NaiveTime,
+    pub close: NaiveTime,
+}
+
+impl TradingHours {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.open <= self.close {
+            self.open <= time && time < self.close
+        } else {
+            // Session runs over midnight
+            time >= self.open || time < self.close
+        }
+    }
+}
+
+fn default_lot_size() -> i32 {
+    1
+}
+
+fn default_price_decimals() -> usize {
+    2
+}
+
+impl Instrument {
+    /// Rounds a simulated price to the nearest tick
+    pub fn round_to_tick(&self, price: f64) -> Option<Price> {
+        Price::from_f64(price).map(|p| p.round_to(self.tick_size))
+    }
+}
+
+/// Reasons an instrument registry cannot be loaded
+#[derive(Debug)]
+pub enum RegistryError {
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+    DuplicateSymbol(String),
+    InvalidTickSize(String),
+    InvalidLotSize(String),
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryError::Io(e) => write!(f, "Cannot read instrument registry: {}", e),
+            RegistryError::Parse(e) => write!(f, "Invalid instrument registry: {}", e),
+            RegistryError::DuplicateSymbol(s) => write!(f, "Instrument {} is listed twice", s),
+            RegistryError::InvalidTickSize(s) => write!(f, "Instrument {} needs a positive tick size", s),
+            RegistryError::InvalidLotSize(s) => write!(f, "Instrument {} needs a positive lot size", s),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+/// Instruments by symbol, loaded from a JSON array of `Instrument`s
+#[derive(Debug, Clone, Default)]
+pub struct InstrumentRegistry {
+    instruments: BTreeMap<String, Instrument>,
+}
+
+impl InstrumentRegistry {
+    pub fn new(instruments: Vec<Instrument>) -> Result<Self, RegistryError> {
+        let mut registry = Self::default();
+        for instrument in instruments {
+            if instrument.tick_size <= Price::ZERO {
+                return Err(RegistryError::InvalidTickSize(instrument.symbol));
+            }
+            if instrument.lot_size <= 0 {
+                return Err(RegistryError::InvalidLotSize(instrument.symbol));
+            }
+            if registry.instruments.contains_key(&instrument.symbol) {
+                return Err(RegistryError::DuplicateSymbol(instrument.symbol));
+            }
+            registry.instruments.insert(instrument.symbol.clone(), instrument);
+        }
+        Ok(registry)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, RegistryError> {
+        Self::new(serde_json::from_str(json).map_err(RegistryError::Parse)?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RegistryError> {
+        Self::from_json(&std::fs::read_to_string(path).map_err(RegistryError::Io)?)
+    }
+
+    /// Loads the file named by `INSTRUMENTS`, or `instruments.json`
+    pub fn from_env() -> Result<Self, RegistryError> {
+        let path = std::env::var("INSTRUMENTS").unwrap_or_else(|_| DEFAULT_REGISTRY_PATH.to_string());
+        Self::load(path)
+    }
+
+    pub fn get(&self, symbol: &str) -> Option<&Instrument> {
+        self.instruments.get(symbol)
+    }
+
+    /// Instruments in symbol order
+    pub fn iter(&self) -> impl Iterator<Item = &Instrument> {
+        self.instruments.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.instruments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instruments.is_empty()
+    }
+}
+
+/// Prices are written as decimals in the registry file rather than raw units
+mod decimal_price {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(price: &Price, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(price.to_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Price, D::Error> {
+        let value = f64::deserialize(deserializer)?;
+        Price::from_f64(value).ok_or_else(|| D::Error::custom(format!("invalid price {}", value)))
+    }
+}
+
+/// A `PriceCollar` with both bounds written as decimals
+mod decimal_collar {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct DecimalCollar {
+        #[serde(with = "decimal_price")]
+        min: Price,
+        #[serde(with = "decimal_price")]
+        max: Price,
+    }
+
+    pub fn serialize<S: Serializer>(collar: &Option<PriceCollar>, serializer: S) -> Result<S::Ok, S::Error> {
+        collar
+            .map(|c| DecimalCollar { min: c.min, max: c.max })
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<PriceCollar>, D::Error> {
+        let collar = Option::<DecimalCollar>::deserialize(deserializer)?;
+        Ok(collar.map(|c| PriceCollar { min: c.min, max: c.max }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::OrderBook;
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn registry_fills_defaults_and_rounds_to_tick() {
+        let registry = InstrumentRegistry::from_json(r#"[{"symbol": "TSLA", "tick_size": 0.05}]"#).unwrap();
+        assert_eq!(registry.len(), 1);
+        let tsla = registry.get("TSLA").unwrap();
+        assert_eq!((tsla.lot_size, tsla.price_decimals, tsla.trading_hours), (1, 2, None));
+        assert_eq!(tsla.round_to_tick(300.03), Price::from_f64(300.05));
+        assert!(registry.get("AAPL").is_none());
+    }
+
+    #[test]
+    fn price_protection_is_copied_to_the_book() {
+        let registry = InstrumentRegistry::from_json(
+            r#"[{
+                "symbol": "TSLA",
+                "tick_size": 0.01,
+                "price_collar": {"min": 100.0, "max": 500.5},
+                "dynamic_collar_bps": 1000,
+                "volatility_guard": {"max_move_bps": 500, "window": 1000, "auction_duration": 2000}
+            }]"#,
+        )
+        .unwrap();
+        let tsla = registry.get("TSLA").unwrap();
+        let collar = PriceCollar { min: Price::from_f64(100.0).unwrap(), max: Price::from_f64(500.5).unwrap() };
+        assert_eq!(tsla.price_collar, Some(collar));
+
+        let book = OrderBook::for_instrument(tsla);
+        assert_eq!(book.price_collar, Some(collar));
+        assert_eq!(book.dynamic_collar_bps, Some(1000));
+        assert_eq!(book.volatility_guard.map(|g| g.auction_duration), Some(2000));
+
+        let written = serde_json::to_value(tsla).unwrap();
+        assert_eq!(written["price_collar"]["max"], 500.5);
+    }
+
+    #[test]
+    fn registry_refuses_bad_entries() {
+        assert!(matches!(
+            InstrumentRegistry::from_json(r#"[{"symbol": "X", "tick_size": 0}]"#),
+            Err(RegistryError::InvalidTickSize(_))
+        ));
+        assert!(matches!(
+            InstrumentRegistry::from_json(r#"[{"symbol": "X", "tick_size": 0.01, "lot_size": 0}]"#),
+            Err(RegistryError::InvalidLotSize(_))
+        ));
+        assert!(matches!(
+            InstrumentRegistry::from_json(r#"[{"symbol": "X", "tick_size": 0.01}, {"symbol": "X", "tick_size": 0.5}]"#),
+            Err(RegistryError::DuplicateSymbol(_))
+        ));
+        assert!(matches!(InstrumentRegistry::from_json("{}"), Err(RegistryError::Parse(_))));
+    }
+
+    #[test]
+    fn trading_hours_may_run_over_midnight() {
+        let day = TradingHours { open: time(13, 30), close: time(20, 0) };
+        assert!(day.contains(time(13, 30)));
+        assert!(!day.contains(time(20, 0)));
+        assert!(!day.contains(time(1, 0)));
+
+        let overnight = TradingHours { open: time(22, 0), close: time(6, 0) };
+        assert!(overnight.contains(time(23, 0)));
+        assert!(overnight.contains(time(5, 59)));
+        assert!(!overnight.contains(time(12, 0)));
+    }
+}
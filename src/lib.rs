@@ -1,3 +1,7 @@
+pub mod instrument;
 pub mod orderbook;
+pub mod price;
+pub mod publishing;
 pub mod messaging;
-pub mod utils; 
\ No newline at end of file
+pub mod utils;
+pub mod validation; 
\ No newline at end of file
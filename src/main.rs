@@ -1,35 +1,263 @@
+use chrono::DateTime;
 use std::collections::HashMap;
-use rust_validator::orderbook::{Order, OrderBook, OrderType, Action};
+use rust_validator::instrument::InstrumentRegistry;
+use rust_validator::orderbook::{BookDelta, Order, OrderBook, OrderEvent, Trade, DEFAULT_TICK_SIZE};
 use rust_validator::utils::now_nanos;
+use rust_validator::validation::{ValidationConfig, Validator};
 use prost::Message;
 use futures_util::stream::StreamExt;
 use std::task::{Context, Poll};
 use futures_util::task::noop_waker;
 use std::env;
+use std::fs::File;
+use std::time::Duration;
+use rust_validator::messaging::{proto, MarketDataConfig, NatsClient};
+use rust_validator::publishing::{Publication, Publisher, PublishingConfig};
 
-mod proto {
-    include!(concat!(env!("OUT_DIR"), "/order.rs"));
+const INITIAL_CAPACITY: usize = 100;
+/// How often the main loop checks for instruments whose session has closed
+const SESSION_CHECK_INTERVAL: u128 = 1_000_000_000;
+
+/// Rules used when `VALIDATION_CONFIG` does not name a JSON config file
+fn default_validation_config() -> ValidationConfig {
+    ValidationConfig {
+        tick_size: Some(DEFAULT_TICK_SIZE),
+        lot_size: Some(1),
+        min_quantity: Some(1),
+        max_quantity: Some(1_000_000),
+        max_clock_skew: Some(Duration::from_secs(1)),
+        max_age: Some(Duration::from_secs(5)),
+        duplicate_id_window: Some(100_000),
+        ..ValidationConfig::default()
+    }
 }
 
-const INITIAL_CAPACITY: usize = 100;
+/// Publishes the trades, order events and level changes produced by one
+/// change to `book`
+async fn publish_book_change<'a>(
+    nats: &NatsClient,
+    book: &OrderBook,
+    trades: impl IntoIterator<Item = &'a Trade>,
+    order_events: &[OrderEvent],
+    delta: Option<&BookDelta>,
+) {
+    if let Err(e) = nats.publish_trades(trades).await {
+        eprintln!("Failed to publish trades: {}", e);
+    }
+    if !order_events.is_empty() {
+        if let Err(e) = nats.publish_order_events(&book.symbol, order_events).await {
+            eprintln!("Failed to publish order events: {}", e);
+        }
+    }
+    if let Some(delta) = delta {
+        if let Err(e) = nats.publish_delta(delta).await {
+            eprintln!("Failed to publish delta: {}", e);
+        }
+    }
+}
+
+/// Routes each incoming order to the book of its instrument, creating books
+/// on first use from the instrument registry, and publishes the resulting
+/// market data
+struct Engine {
+    registry: InstrumentRegistry,
+    validator: Validator,
+    nats: NatsClient,
+    publisher: Publisher,
+    order_books: HashMap<String, OrderBook>,
+    /// Orders refused because their instrument is not in the registry, by symbol
+    unknown_instruments: HashMap<String, u64>,
+    /// Whether each book's session was open when last checked
+    in_session: HashMap<String, bool>,
+}
+
+impl Engine {
+    fn new(registry: InstrumentRegistry, validator: Validator, nats: NatsClient, publisher: Publisher) -> Self {
+        Self {
+            registry,
+            validator,
+            nats,
+            publisher,
+            order_books: HashMap::with_capacity(INITIAL_CAPACITY),
+            unknown_instruments: HashMap::new(),
+            in_session: HashMap::new(),
+        }
+    }
+
+    /// Expires the DAY orders of every book whose instrument's trading hours
+    /// closed since the last check. `now` is in nanoseconds since the epoch.
+    /// Books never checked before count as having been in session.
+    async fn close_sessions(&mut self, now: u128) {
+        let time = DateTime::from_timestamp_nanos(now as i64).time();
+        let mut due = Vec::new();
+        for (symbol, book) in &mut self.order_books {
+            let Some(hours) = self.registry.get(symbol).and_then(|i| i.trading_hours) else {
+                continue;
+            };
+            let open = hours.contains(time);
+            let was_open = self.in_session.insert(symbol.clone(), open).unwrap_or(true);
+            if was_open && !open {
+                for report in book.end_session() {
+                    println!(
+                        "Order id {} expired at {} session close ({} cancelled)",
+                        report.order_id, symbol, report.cancelled
+                    );
+                    publish_book_change(&self.nats, book, [], &report.order_events, report.delta.as_ref()).await;
+                    if report.delta.is_some() {
+                        due.extend(self.publisher.on_book_change(book, now_nanos()));
+                    }
+                }
+            }
+        }
+        self.publish_book_updates(due).await;
+    }
+
+    /// Uncrosses every book whose volatility auction is due by `now`
+    async fn end_auctions(&mut self, now: u128) {
+        let mut due = Vec::new();
+        for book in self.order_books.values_mut() {
+            if book.auction_end().is_none_or(|end| end > now) {
+                continue;
+            }
+            let report = book.uncross(now);
+            let trades = || report.trades.iter().chain(report.triggered.iter().flat_map(|r| r.trades.iter()));
+            for trade in trades() {
+                println!("{}", trade);
+            }
+            if let Some(change) = &report.status_change {
+                println!("{} moved from {:?} to {:?}", change.symbol, change.from, change.to);
+            }
+            publish_book_change(&self.nats, book, trades(), &report.order_events, report.delta.as_ref()).await;
+            if report.delta.is_some() {
+                due.extend(self.publisher.on_book_change(book, now_nanos()));
+            }
+        }
+        self.publish_book_updates(due).await;
+    }
+
+    async fn handle_message(&mut self, payload: &[u8]) {
+        let start = now_nanos();
+        // Try to decode the message data
+        let proto_order = match proto::Order::decode(payload) {
+            Ok(order) => order,
+            Err(e) => {
+                eprintln!("Failed to decode message: {}", e);
+                eprintln!("Raw message data: {:?}", payload);
+                return;
+            }
+        };
+        let order = match Order::try_from(&proto_order) {
+            Ok(order) => order,
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        };
+        let Some(instrument) = self.registry.get(&order.instrument) else {
+            let count = self.unknown_instruments.entry(order.instrument.clone()).or_insert(0);
+            *count += 1;
+            println!(
+                "Order id {} rejected: unknown instrument {} ({} so far)",
+                order.id, order.instrument, count
+            );
+            return;
+        };
+        let book = self
+            .order_books
+            .entry(instrument.symbol.clone())
+            .or_insert_with(|| OrderBook::for_instrument(instrument));
+        if let Err(rule) = self.validator.validate(&order, Some(instrument), book.last_trade_price(), start) {
+            println!(
+                "Order id {} failed {} check ({} so far)",
+                order.id, rule, self.validator.violations()[&rule]
+            );
+            return;
+        }
+        match book.add_order(&order) {
+            Ok(report) => {
+                for trade in report.all_trades() {
+                    println!("{}", trade);
+                }
+                let status_changes = std::iter::once(&report)
+                    .chain(&report.triggered)
+                    .filter_map(|r| r.status_change.as_ref());
+                for change in status_changes {
+                    println!("{} moved from {:?} to {:?}", change.symbol, change.from, change.to);
+                }
+                let (events, delta) = (&report.order_events, report.delta.as_ref());
+                publish_book_change(&self.nats, book, report.all_trades(), events, delta).await;
+                if report.delta.is_some() {
+                    let due = self.publisher.on_book_change(book, start);
+                    self.publish_book_updates(due).await;
+                }
+            }
+            Err(reason) => println!("Order id {} rejected: {}", order.id, reason),
+        }
+        let decimals = instrument.price_decimals;
+        let inter_service_latency_us = (start - order.timestamp) as i32 / 1000;
+        let end = now_nanos();
+        let processing_time_us = (end - start) as i32 / 1000;
+        println!(
+            "Order id {}: {:<4} {:<4} {:>4} @ {:.*} | inter-service latency: {} us | processing: {} us",
+            &order.id,
+            &order.instrument.chars().take(4).collect::<String>(),
+            format!("{:<4}", format!("{:?}", order.action)).chars().take(4).collect::<String>(),
+            format!("{:>4}", order.amount),
+            decimals,
+            order.price,
+            inter_service_latency_us,
+            processing_time_us
+        );
+    }
 
-fn is_important_update(new: &OrderBook, last: &OrderBook) -> bool {
-    if new.bids.is_empty() || new.asks.is_empty() || last.bids.is_empty() || last.asks.is_empty() {
-        return true;
+    /// Sends conflated book updates whose window has closed
+    async fn flush_conflated(&mut self) {
+        if self.publisher.has_pending() {
+            let due = self.publisher.poll(now_nanos());
+            self.publish_book_updates(due).await;
+        }
+    }
+
+    async fn publish_book_updates(&self, publications: Vec<Publication>) {
+        for publication in publications {
+            let update = &publication.update;
+            let decimals = self.registry.get(&update.symbol).map_or(2, |i| i.price_decimals);
+            let level = |l: &proto::PriceLevel| format!("{:.*} x {}", decimals, l.price, l.amount);
+            let best_bid = update.bids.first().map(level).unwrap_or("None".to_string());
+            let best_ask = update.asks.first().map(level).unwrap_or("None".to_string());
+            println!(
+                "{} BOOK {} | Bid: {} | Ask: {}",
+                update.symbol,
+                publication.subscriber.to_uppercase(),
+                best_bid,
+                best_ask
+            );
+            if let Err(e) = self.nats.publish_orderbook(&publication.subject, update).await {
+                eprintln!("Failed to publish {} update: {}", publication.subscriber, e);
+            }
+        }
     }
-    
-    let bid_diff = (new.bids[0].price - last.bids[0].price).abs();
-    let ask_diff = (new.asks[0].price - last.asks[0].price).abs();
-    
-    bid_diff > 0.01 || ask_diff > 0.01
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut order_books: HashMap<String, OrderBook> = HashMap::with_capacity(INITIAL_CAPACITY);
-    let mut last_updates: HashMap<String, OrderBook> = HashMap::with_capacity(INITIAL_CAPACITY);
-    let client = async_nats::connect("localhost:4222").await?;
-    let mut subscription = client.subscribe("market_data".to_string()).await?;
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let validation_config = match env::var("VALIDATION_CONFIG") {
+        Ok(path) => serde_json::from_reader(File::open(path)?)?,
+        Err(_) => default_validation_config(),
+    };
+    let market_data_config: MarketDataConfig = match env::var("MARKET_DATA_CONFIG") {
+        Ok(path) => serde_json::from_reader(File::open(path)?)?,
+        Err(_) => MarketDataConfig::default(),
+    };
+    let publishing_config: PublishingConfig = match env::var("PUBLISHING_CONFIG") {
+        Ok(path) => serde_json::from_reader(File::open(path)?)?,
+        Err(_) => PublishingConfig::for_market_data(&market_data_config),
+    };
+    let registry = InstrumentRegistry::from_env()?;
+    let nats = NatsClient::with_config("localhost:4222", market_data_config).await?;
+    let mut subscription = nats.subscribe("market_data").await?;
+    let publisher = Publisher::new(publishing_config);
+    let mut engine = Engine::new(registry, Validator::new(validation_config), nats, publisher);
 
     println!("Validator started. Waiting for market data...");
 
@@ -37,153 +265,136 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let busy_mode = env::var("BUSY_MODE").unwrap_or_else(|_| "1".to_string()) == "1";
     println!("Busy mode: {}", busy_mode);
 
-    // BUSY-POLLING LOOP: This loop will continuously poll for new messages without yielding or sleeping,
-    // maximizing CPU usage for lowest possible message latency.
+    // Poll the subscription without yielding to the runtime. Busy mode spins between messages,
+    // maximizing CPU usage for lowest possible message latency; otherwise the loop sleeps briefly.
     let waker = noop_waker();
     let mut cx = Context::from_waker(&waker);
+    let mut last_session_check = 0;
     loop {
-        if busy_mode {
-            // BUSY-POLLING: maximize CPU usage for lowest latency
-            match StreamExt::poll_next_unpin(&mut subscription, &mut cx) {
-                Poll::Ready(Some(message)) => {
-                    let start = now_nanos();
-                    // Try to decode the message data
-                    let proto_order = match proto::Order::decode(message.payload.as_ref()) {
-                        Ok(order) => order,
-                        Err(e) => {
-                            eprintln!("Failed to decode message: {}", e);
-                            eprintln!("Raw message data: {:?}", message.payload);
-                            continue;
-                        }
-                    };
-                    let order = Order {
-                        id: proto_order.id as u128,
-                        price: proto_order.price,
-                        amount: proto_order.amount,
-                        action: match proto_order.action {
-                            0 => Action::Buy,
-                            1 => Action::Sell,
-                            _ => {
-                                eprintln!("Invalid action value: {}", proto_order.action);
-                                continue;
-                            }
-                        },
-                        order_type: match proto_order.order_type {
-                            0 => OrderType::Market,
-                            1 => OrderType::Limit,
-                            2 => OrderType::Cancel,
-                            _ => {
-                                eprintln!("Invalid order type value: {}", proto_order.order_type);
-                                continue;
-                            }
-                        },
-                        timestamp: proto_order.timestamp as u128,
-                        instrument: proto_order.instrument.clone(),
-                    };
-                    let book = order_books.entry("TSLA".to_string()).or_insert_with(|| OrderBook::new("TSLA".to_string()));
-                    book.add_order(&order);
-                    let update = book.get_book_update();
-                    let should_publish = last_updates
-                        .get("TSLA")
-                        .map(|last| is_important_update(update, last))
-                        .unwrap_or(true);
-                    if should_publish {
-                        let best_bid = update.bids.first().map(|b| format!("{:.2} x {}", b.price, b.total_amount)).unwrap_or("None".to_string());
-                        let best_ask = update.asks.first().map(|a| format!("{:.2} x {}", a.price, a.total_amount)).unwrap_or("None".to_string());
-                        println!("TSLA BOOK TOP | Bid: {} | Ask: {}", best_bid, best_ask);
-                    }
-                    if should_publish {
-                        last_updates.insert("TSLA".to_string(), update.clone());
-                    }
-                    let inter_service_latency_us = (start - order.timestamp) as i32 / 1000;
-                    let end = now_nanos();
-                    let processing_time_us = (end - start) as i32 / 1000;
-                    println!(
-                        "Order id {}: {:<4} {:<4} {:>4} @ {:.2} | inter-service latency: {} us | processing: {} us",
-                        &order.id,
-                        &"TSLA".chars().take(4).collect::<String>(),
-                        format!("{:<4}", format!("{:?}", order.action)).chars().take(4).collect::<String>(),
-                        format!("{:>4}", order.amount),
-                        order.price,
-                        inter_service_latency_us,
-                        processing_time_us
-                    );
-                }
-                Poll::Ready(None) | Poll::Pending => {
-                    std::hint::spin_loop();
-                }
+        let now = now_nanos();
+        if now - last_session_check >= SESSION_CHECK_INTERVAL {
+            engine.close_sessions(now).await;
+            last_session_check = now;
+        }
+        engine.end_auctions(now).await;
+        match StreamExt::poll_next_unpin(&mut subscription, &mut cx) {
+            Poll::Ready(Some(message)) => {
+                engine.handle_message(message.payload.as_ref()).await;
+                // Windows also close for symbols that stopped changing while others stay busy
+                engine.flush_conflated().await;
             }
-        } else {
-            // SLEEPING: poll for messages, but sleep briefly if none are available
-            match StreamExt::poll_next_unpin(&mut subscription, &mut cx) {
-                Poll::Ready(Some(message)) => {
-                    let start = now_nanos();
-                    // Try to decode the message data
-                    let proto_order = match proto::Order::decode(message.payload.as_ref()) {
-                        Ok(order) => order,
-                        Err(e) => {
-                            eprintln!("Failed to decode message: {}", e);
-                            eprintln!("Raw message data: {:?}", message.payload);
-                            continue;
-                        }
-                    };
-                    let order = Order {
-                        id: proto_order.id as u128,
-                        price: proto_order.price,
-                        amount: proto_order.amount,
-                        action: match proto_order.action {
-                            0 => Action::Buy,
-                            1 => Action::Sell,
-                            _ => {
-                                eprintln!("Invalid action value: {}", proto_order.action);
-                                continue;
-                            }
-                        },
-                        order_type: match proto_order.order_type {
-                            0 => OrderType::Market,
-                            1 => OrderType::Limit,
-                            2 => OrderType::Cancel,
-                            _ => {
-                                eprintln!("Invalid order type value: {}", proto_order.order_type);
-                                continue;
-                            }
-                        },
-                        timestamp: proto_order.timestamp as u128,
-                        instrument: proto_order.instrument.clone(),
-                    };
-                    let book = order_books.entry("TSLA".to_string()).or_insert_with(|| OrderBook::new("TSLA".to_string()));
-                    book.add_order(&order);
-                    let update = book.get_book_update();
-                    let should_publish = last_updates
-                        .get("TSLA")
-                        .map(|last| is_important_update(update, last))
-                        .unwrap_or(true);
-                    if should_publish {
-                        let best_bid = update.bids.first().map(|b| format!("{:.2} x {}", b.price, b.total_amount)).unwrap_or("None".to_string());
-                        let best_ask = update.asks.first().map(|a| format!("{:.2} x {}", a.price, a.total_amount)).unwrap_or("None".to_string());
-                        println!("TSLA BOOK TOP | Bid: {} | Ask: {}", best_bid, best_ask);
-                    }
-                    if should_publish {
-                        last_updates.insert("TSLA".to_string(), update.clone());
-                    }
-                    let inter_service_latency_us = (start - order.timestamp) as i32 / 1000;
-                    let end = now_nanos();
-                    let processing_time_us = (end - start) as i32 / 1000;
-                    println!(
-                        "Order id {}: {:<4} {:<4} {:>4} @ {:.2} | inter-service latency: {} us | processing: {} us",
-                        &order.id,
-                        &"TSLA".chars().take(4).collect::<String>(),
-                        format!("{:<4}", format!("{:?}", order.action)).chars().take(4).collect::<String>(),
-                        format!("{:>4}", order.amount),
-                        order.price,
-                        inter_service_latency_us,
-                        processing_time_us
-                    );
-                }
-                Poll::Ready(None) | Poll::Pending => {
+            Poll::Ready(None) | Poll::Pending => {
+                engine.flush_conflated().await;
+                if busy_mode {
+                    // BUSY-POLLING: maximize CPU usage for lowest latency
+                    std::hint::spin_loop();
+                } else {
+                    // SLEEPING: sleep briefly if no messages are available
                     std::thread::sleep(std::time::Duration::from_millis(1));
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_validator::orderbook::TradingStatus;
+    use rust_validator::price::Price;
+
+    /// Engine over the instruments in `registry`. Its NATS client never
+    /// reaches a server; publications queue up unsent.
+    async fn engine(registry: &str) -> Engine {
+        let client = async_nats::ConnectOptions::new()
+            .retry_on_initial_connect()
+            .connect("localhost:4222")
+            .await
+            .unwrap();
+        let nats = NatsClient::with_client(client, MarketDataConfig::default());
+        let registry = InstrumentRegistry::from_json(registry).unwrap();
+        let publisher = Publisher::new(PublishingConfig::default());
+        Engine::new(registry, Validator::new(ValidationConfig::default()), nats, publisher)
+    }
+
+    fn payload(id: u64, instrument: &str, price: f64, action: proto::Action) -> Vec<u8> {
+        proto::Order {
+            id,
+            price,
+            amount: 5,
+            action: action as i32,
+            order_type: proto::OrderType::Limit as i32,
+            timestamp: now_nanos() as u64,
+            instrument: instrument.to_string(),
+            ..proto::Order::default()
+        }
+        .encode_to_vec()
+    }
+
+    #[tokio::test]
+    async fn orders_are_routed_to_the_book_of_their_instrument() {
+        let mut engine = engine(r#"[{"symbol": "TSLA", "tick_size": 0.01}, {"symbol": "AAPL", "tick_size": 0.05}]"#).await;
+        engine.handle_message(&payload(1, "TSLA", 300.0, proto::Action::Buy)).await;
+        engine.handle_message(&payload(2, "AAPL", 300.0, proto::Action::Sell)).await;
+        engine.handle_message(&payload(3, "MSFT", 300.0, proto::Action::Sell)).await;
+        engine.handle_message(&payload(4, "MSFT", 300.0, proto::Action::Sell)).await;
+
+        // Each book only sees its own symbol, so the two orders do not trade
+        let tsla = &engine.order_books["TSLA"];
+        let aapl = &engine.order_books["AAPL"];
+        assert_eq!(tsla.best_bid().unwrap().total_amount, 5);
+        assert!(tsla.best_ask().is_none());
+        assert_eq!(aapl.best_ask().unwrap().total_amount, 5);
+        assert_eq!(aapl.tick_size, Price::from_units(50_000));
+
+        assert!(!engine.order_books.contains_key("MSFT"));
+        assert_eq!(engine.unknown_instruments["MSFT"], 2);
+        assert_eq!(engine.publisher.cache().len(), 2);
+        assert_eq!(engine.publisher.cache().get("AAPL").unwrap().asks[0].amount, 5);
+    }
+
+    #[tokio::test]
+    async fn volatility_auctions_uncross_when_due() {
+        let mut engine = engine(
+            r#"[{"symbol": "TSLA", "tick_size": 0.01, "volatility_guard": {"max_move_bps": 500, "window": 1000000000000, "auction_duration": 1000000000}}]"#,
+        )
+        .await;
+        engine.handle_message(&payload(1, "TSLA", 300.0, proto::Action::Sell)).await;
+        engine.handle_message(&payload(2, "TSLA", 400.0, proto::Action::Sell)).await;
+        engine.handle_message(&payload(3, "TSLA", 400.0, proto::Action::Buy)).await;
+        engine.handle_message(&payload(4, "TSLA", 400.0, proto::Action::Buy)).await;
+
+        let book = &engine.order_books["TSLA"];
+        assert_eq!(book.trading_status(), TradingStatus::Auction);
+        let end = book.auction_end().unwrap();
+
+        engine.end_auctions(end - 1).await;
+        assert_eq!(engine.order_books["TSLA"].trading_status(), TradingStatus::Auction);
+        engine.end_auctions(end).await;
+        let book = &engine.order_books["TSLA"];
+        assert_eq!(book.trading_status(), TradingStatus::Open);
+        assert_eq!(book.last_trade_price(), Price::from_f64(400.0));
+    }
+
+    #[tokio::test]
+    async fn day_orders_expire_when_their_session_closes() {
+        let mut engine = engine(
+            r#"[{"symbol": "TSLA", "tick_size": 0.01, "trading_hours": {"open": "13:30:00", "close": "20:00:00"}}]"#,
+        )
+        .await;
+        let mut day = proto::Order::decode(payload(1, "TSLA", 300.0, proto::Action::Buy).as_slice()).unwrap();
+        day.time_in_force = proto::TimeInForce::Day as i32;
+        engine.handle_message(&day.encode_to_vec()).await;
+        engine.handle_message(&payload(2, "TSLA", 299.0, proto::Action::Buy)).await;
+
+        let hour = 3_600_000_000_000;
+        engine.close_sessions(14 * hour).await;
+        assert!(engine.order_books["TSLA"].get_order(1).is_some());
+
+        engine.close_sessions(20 * hour).await;
+        let book = &engine.order_books["TSLA"];
+        assert!(book.get_order(1).is_none());
+        assert!(book.get_order(2).is_some());
+        assert!(!engine.in_session["TSLA"]);
+    }
+}
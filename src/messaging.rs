@@ -1,51 +1,404 @@
 use async_nats;
+use async_nats::HeaderMap;
 use prost::Message;
-use crate::orderbook::{Order, OrderBook};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use crate::orderbook::{
+    Action, BookDelta, LevelChange, Order, OrderEvent, OrderEventKind, OrderType, TimeInForce, Trade,
+};
+use crate::price::Price;
 
 pub mod proto {
     include!(concat!(env!("OUT_DIR"), "/order.rs"));
 }
 
+/// Reasons a wire order cannot be turned into an `orderbook::Order`
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    InvalidAction(i32),
+    InvalidOrderType(i32),
+    InvalidTimeInForce(i32),
+    InvalidPrice(f64),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::InvalidAction(v) => write!(f, "Invalid action value: {}", v),
+            DecodeError::InvalidOrderType(v) => write!(f, "Invalid order type value: {}", v),
+            DecodeError::InvalidTimeInForce(v) => write!(f, "Invalid time in force value: {}", v),
+            DecodeError::InvalidPrice(v) => write!(f, "Invalid price value: {}", v),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl TryFrom<&proto::Order> for Order {
+    type Error = DecodeError;
+
+    fn try_from(proto_order: &proto::Order) -> Result<Self, Self::Error> {
+        Ok(Order {
+            id: proto_order.id as u128,
+            price: Price::from_f64(proto_order.price)
+                .ok_or(DecodeError::InvalidPrice(proto_order.price))?,
+            amount: proto_order.amount,
+            action: match proto_order.action {
+                0 => Action::Buy,
+                1 => Action::Sell,
+                v => return Err(DecodeError::InvalidAction(v)),
+            },
+            order_type: match proto_order.order_type {
+                0 => OrderType::Market,
+                1 => OrderType::Limit,
+                2 => OrderType::Cancel,
+                3 => OrderType::Replace,
+                4 => OrderType::Stop,
+                5 => OrderType::StopLimit,
+                v => return Err(DecodeError::InvalidOrderType(v)),
+            },
+            timestamp: proto_order.timestamp as u128,
+            instrument: proto_order.instrument.clone(),
+            time_in_force: match proto_order.time_in_force {
+                0 => TimeInForce::Gtc,
+                1 => TimeInForce::Ioc,
+                2 => TimeInForce::Fok,
+                3 => TimeInForce::Day,
+                v => return Err(DecodeError::InvalidTimeInForce(v)),
+            },
+            display_amount: proto_order.display_amount,
+            hidden_amount: 0,
+            stop_price: Price::from_f64(proto_order.stop_price)
+                .ok_or(DecodeError::InvalidPrice(proto_order.stop_price))?,
+            owner: proto_order.owner.clone(),
+            post_only: proto_order.post_only,
+        })
+    }
+}
+
+impl From<&Order> for proto::Order {
+    fn from(order: &Order) -> Self {
+        proto::Order {
+            id: order.id as u64,
+            price: order.price.to_f64(),
+            amount: order.amount,
+            action: match order.action {
+                Action::Buy => proto::Action::Buy as i32,
+                Action::Sell => proto::Action::Sell as i32,
+            },
+            order_type: match order.order_type {
+                OrderType::Market => proto::OrderType::Market as i32,
+                OrderType::Limit => proto::OrderType::Limit as i32,
+                OrderType::Cancel => proto::OrderType::Cancel as i32,
+                OrderType::Replace => proto::OrderType::Replace as i32,
+                OrderType::Stop => proto::OrderType::Stop as i32,
+                OrderType::StopLimit => proto::OrderType::StopLimit as i32,
+            },
+            timestamp: order.timestamp as u64,
+            instrument: order.instrument.clone(),
+            time_in_force: match order.time_in_force {
+                TimeInForce::Gtc => proto::TimeInForce::Gtc as i32,
+                TimeInForce::Ioc => proto::TimeInForce::Ioc as i32,
+                TimeInForce::Fok => proto::TimeInForce::Fok as i32,
+                TimeInForce::Day => proto::TimeInForce::Day as i32,
+            },
+            display_amount: order.display_amount,
+            stop_price: order.stop_price.to_f64(),
+            owner: order.owner.clone(),
+            post_only: order.post_only,
+        }
+    }
+}
+
+impl From<&BookDelta> for proto::BookDelta {
+    fn from(delta: &BookDelta) -> Self {
+        proto::BookDelta {
+            symbol: delta.symbol.clone(),
+            sequence: delta.sequence,
+            timestamp: delta.timestamp as u64,
+            levels: delta
+                .levels
+                .iter()
+                .map(|level| proto::LevelDelta {
+                    side: match level.side {
+                        Action::Buy => proto::Action::Buy as i32,
+                        Action::Sell => proto::Action::Sell as i32,
+                    },
+                    price: level.price.to_f64(),
+                    amount: level.amount,
+                    change: match level.change {
+                        LevelChange::New => proto::LevelChange::LevelNew as i32,
+                        LevelChange::Change => proto::LevelChange::LevelChange as i32,
+                        LevelChange::Delete => proto::LevelChange::LevelDelete as i32,
+                    },
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<&OrderEvent> for proto::OrderEvent {
+    fn from(event: &OrderEvent) -> Self {
+        let kind = match event.kind {
+            OrderEventKind::Add => proto::OrderEventKind::OrderAdd,
+            OrderEventKind::Execute => proto::OrderEventKind::OrderExecute,
+            OrderEventKind::Reduce => proto::OrderEventKind::OrderReduce,
+            OrderEventKind::Delete => proto::OrderEventKind::OrderDelete,
+        };
+        let side = match event.side {
+            Action::Buy => proto::Action::Buy,
+            Action::Sell => proto::Action::Sell,
+        };
+        proto::OrderEvent {
+            sequence: event.sequence,
+            kind: kind as i32,
+            order_id: event.order_id as u64,
+            side: side as i32,
+            price: event.price.to_f64(),
+            amount: event.amount,
+            queue_position: event.queue_position as u32,
+            timestamp: event.timestamp as u64,
+        }
+    }
+}
+
+/// Venue used in market data subjects unless configured otherwise
+pub const DEFAULT_VENUE: &str = "NYSE";
+/// Levels per side in published book updates unless configured otherwise
+pub const DEFAULT_BOOK_DEPTH: usize = 10;
+/// Header carrying the payload encoding of published market data
+pub const ENCODING_HEADER: &str = "Content-Type";
+
+/// Payload encoding of published market data, announced in `ENCODING_HEADER`
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum Encoding {
+    #[default]
+    Protobuf,
+    /// Human readable, for debugging
+    Json,
+}
+
+impl Encoding {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Encoding::Protobuf => "application/x-protobuf",
+            Encoding::Json => "application/json",
+        }
+    }
+
+    pub fn encode<M: Message + Serialize>(self, message: &M) -> Result<Vec<u8>, serde_json::Error> {
+        match self {
+            Encoding::Protobuf => Ok(message.encode_to_vec()),
+            Encoding::Json => serde_json::to_vec(message),
+        }
+    }
+}
+
+/// Subject templates for market data. `{venue}` and `{symbol}` are
+/// replaced when publishing.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct Subjects {
+    /// Best bid and offer, used by the default "top" subscriber
+    pub top_of_book: String,
+    /// Top `book_depth` levels, used by the default "book" subscriber
+    pub book: String,
+    /// One `Trade` per fill
+    pub trades: String,
+    /// L3 `OrderEvents`
+    pub order_events: String,
+    /// Sequenced L2 `BookDelta`s
+    pub deltas: String,
+}
+
+impl Default for Subjects {
+    fn default() -> Self {
+        Self {
+            top_of_book: "top.{venue}.{symbol}".to_string(),
+            book: "book.{venue}.{symbol}".to_string(),
+            trades: "trades.{venue}.{symbol}".to_string(),
+            order_events: "orders.{venue}.{symbol}".to_string(),
+            deltas: "deltas.{venue}.{symbol}".to_string(),
+        }
+    }
+}
+
+/// Where and how `NatsClient` publishes market data
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct MarketDataConfig {
+    /// Venue segment of market data subjects
+    pub venue: String,
+    pub encoding: Encoding,
+    /// Levels per side published by the default "book" subscriber
+    pub book_depth: usize,
+    pub subjects: Subjects,
+}
+
+impl Default for MarketDataConfig {
+    fn default() -> Self {
+        Self {
+            venue: DEFAULT_VENUE.to_string(),
+            encoding: Encoding::default(),
+            book_depth: DEFAULT_BOOK_DEPTH,
+            subjects: Subjects::default(),
+        }
+    }
+}
+
+impl MarketDataConfig {
+    /// Fills a subject template in for `symbol`
+    pub fn subject(&self, template: &str, symbol: &str) -> String {
+        template.replace("{venue}", &self.venue).replace("{symbol}", symbol)
+    }
+}
+
+impl From<&Trade> for proto::Trade {
+    fn from(trade: &Trade) -> Self {
+        let side = match trade.side {
+            Action::Buy => proto::Action::Buy,
+            Action::Sell => proto::Action::Sell,
+        };
+        proto::Trade {
+            symbol: trade.symbol.clone(),
+            aggressor_id: trade.aggressor_id as u64,
+            passive_id: trade.passive_id as u64,
+            price: trade.price.to_f64(),
+            amount: trade.amount,
+            side: side as i32,
+            timestamp: trade.timestamp as u64,
+        }
+    }
+}
+
 pub struct NatsClient {
     client: async_nats::Client,
+    pub config: MarketDataConfig,
 }
 
 impl NatsClient {
     pub async fn new(url: &str) -> Result<Self, async_nats::Error> {
+        Self::with_config(url, MarketDataConfig::default()).await
+    }
+
+    pub async fn with_config(url: &str, config: MarketDataConfig) -> Result<Self, async_nats::Error> {
         let client = async_nats::connect(url).await?;
-        Ok(Self { client })
+        Ok(Self::with_client(client, config))
+    }
+
+    /// Wraps an already configured connection
+    pub fn with_client(client: async_nats::Client, config: MarketDataConfig) -> Self {
+        Self { client, config }
     }
 
     pub async fn subscribe(&self, subject: &str) -> Result<async_nats::Subscriber, Box<dyn std::error::Error + Send + Sync>> {
         self.client.subscribe(subject.to_string()).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
     }
 
-    pub async fn publish_orderbook(&self, subject: &str, book: &OrderBook) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        // Serialize the OrderBook struct using serde_json for now
-        let json = serde_json::to_string(book)?;
-        self.client.publish(subject.into(), json.into()).await?;
+    /// Publishes a `BookUpdate` on the subject template `subject`, filled in
+    /// for the update's symbol
+    pub async fn publish_orderbook(
+        &self,
+        subject: &str,
+        update: &proto::BookUpdate,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let subject = self.config.subject(subject, &update.symbol);
+        self.publish_market_data(subject, update).await
+    }
+
+    /// Publishes one `Trade` message per fill on the trades subject
+    pub async fn publish_trades<'a>(
+        &self,
+        trades: impl IntoIterator<Item = &'a Trade>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        for trade in trades {
+            let subject = self.config.subject(&self.config.subjects.trades, &trade.symbol);
+            self.publish_market_data(subject, &proto::Trade::from(trade)).await?;
+        }
         Ok(())
     }
 
-    pub async fn publish_order(&self, subject: &str, order: &Order) -> Result<(), async_nats::Error> {
-        let proto_order = proto::Order {
-            id: order.id as u64,
-            price: order.price,
-            amount: order.amount,
-            action: match order.action {
-                crate::orderbook::Action::Buy => 0,
-                crate::orderbook::Action::Sell => 1,
-            },
-            order_type: match order.order_type {
-                crate::orderbook::OrderType::Market => 0,
-                crate::orderbook::OrderType::Limit => 1,
-                crate::orderbook::OrderType::Cancel => 2,
-            },
-            timestamp: order.timestamp as u64,
-            instrument: order.instrument.clone(),
+    /// Publishes `message` in the client's encoding, announced in `ENCODING_HEADER`
+    async fn publish_market_data<M: Message + Serialize>(
+        &self,
+        subject: String,
+        message: &M,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let encoding = self.config.encoding;
+        let payload = encoding.encode(message)?;
+        let mut headers = HeaderMap::new();
+        headers.insert(ENCODING_HEADER, encoding.content_type());
+        self.client.publish_with_headers(subject, headers, payload.into()).await?;
+        Ok(())
+    }
+
+    /// Publishes a level delta as a `BookDelta` on the deltas subject
+    pub async fn publish_delta(&self, delta: &BookDelta) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let subject = self.config.subject(&self.config.subjects.deltas, &delta.symbol);
+        self.publish_market_data(subject, &proto::BookDelta::from(delta)).await
+    }
+
+    /// Publishes a batch of L3 events as one `OrderEvents` message on the
+    /// order events subject
+    pub async fn publish_order_events(
+        &self,
+        symbol: &str,
+        events: &[OrderEvent],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let message = proto::OrderEvents {
+            symbol: symbol.to_string(),
+            events: events.iter().map(proto::OrderEvent::from).collect(),
         };
+        let subject = self.config.subject(&self.config.subjects.order_events, symbol);
+        self.publish_market_data(subject, &message).await
+    }
+
+    pub async fn publish_order(&self, subject: &str, order: &Order) -> Result<(), async_nats::Error> {
+        let proto_order = proto::Order::from(order);
         let buf = proto_order.encode_to_vec();
         self.client.publish(subject.into(), buf.into()).await?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update() -> proto::BookUpdate {
+        proto::BookUpdate {
+            symbol: "TSLA".to_string(),
+            bids: vec![proto::PriceLevel { price: 10.5, amount: 3 }],
+            asks: vec![],
+            timestamp: 7,
+            sequence: 2,
+        }
+    }
+
+    #[test]
+    fn book_updates_encode_as_protobuf_or_json() {
+        let bytes = Encoding::Protobuf.encode(&update()).unwrap();
+        assert_eq!(proto::BookUpdate::decode(bytes.as_slice()).unwrap(), update());
+
+        let json: serde_json::Value = serde_json::from_slice(&Encoding::Json.encode(&update()).unwrap()).unwrap();
+        assert_eq!(json["symbol"], "TSLA");
+        assert_eq!(json["bids"][0]["price"], 10.5);
+        assert_eq!(json["sequence"], 2);
+
+        assert_eq!(Encoding::default(), Encoding::Protobuf);
+        assert_eq!(Encoding::Protobuf.content_type(), "application/x-protobuf");
+        assert_eq!(Encoding::Json.content_type(), "application/json");
+    }
+
+    #[test]
+    fn subjects_are_filled_in_per_venue_and_symbol() {
+        let config = MarketDataConfig::default();
+        assert_eq!(config.subject(&config.subjects.top_of_book, "TSLA"), "top.NYSE.TSLA");
+        assert_eq!(config.subject(&config.subjects.book, "TSLA"), "book.NYSE.TSLA");
+
+        let config: MarketDataConfig =
+            serde_json::from_str(r#"{"venue": "XNAS", "subjects": {"trades": "prints.{symbol}.{venue}"}}"#).unwrap();
+        assert_eq!(config.subject(&config.subjects.trades, "AAPL"), "prints.AAPL.XNAS");
+        assert_eq!(config.subject(&config.subjects.deltas, "AAPL"), "deltas.XNAS.AAPL");
+        assert_eq!(config.book_depth, DEFAULT_BOOK_DEPTH);
+    }
+}
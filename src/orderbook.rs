@@ -1,6 +1,20 @@
+use crate::instrument::Instrument;
+use crate::messaging::proto;
+use crate::price::Price;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::sync::Arc;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+mod auction;
+mod matching;
+#[cfg(test)]
+pub(crate) mod tests;
+
+pub use auction::{IndicativeUncross, UncrossReport};
+pub use matching::{Fifo, MatchingPolicy, ProRata, ProRataWithTop};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
 pub enum Action {
     Buy,
     Sell,
@@ -11,194 +25,1430 @@ pub enum OrderType {
     Market,
     Limit,
     Cancel,
+    /// Amends price and/or quantity of the resting order with the same id
+    Replace,
+    /// Becomes a market order once the last trade reaches `stop_price`
+    Stop,
+    /// Becomes a limit order at `price` once the last trade reaches `stop_price`
+    StopLimit,
+}
+
+/// How long an order may rest before its unfilled quantity is removed
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum TimeInForce {
+    /// Good till cancelled
+    #[default]
+    Gtc,
+    /// Immediate or cancel: fill what crosses now, cancel the rest
+    Ioc,
+    /// Fill or kill: fill completely now or not at all
+    Fok,
+    /// Expires at the end of the trading session
+    Day,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Order {
     pub id: u128,
-    pub price: f64,
+    pub price: Price,
     pub amount: i32,
     pub action: Action,
     pub order_type: OrderType,
     pub timestamp: u128,
     pub instrument: String,
+    #[serde(default)]
+    pub time_in_force: TimeInForce,
+    /// Peak size shown on the book for iceberg orders; zero shows the full amount
+    #[serde(default)]
+    pub display_amount: i32,
+    /// Undisplayed reserve of a resting iceberg. Always zero on incoming orders.
+    #[serde(default)]
+    pub hidden_amount: i32,
+    /// Trigger price of stop and stop-limit orders
+    #[serde(default)]
+    pub stop_price: Price,
+    /// Participant or account the order belongs to, used for self-trade
+    /// prevention. Orders with an empty owner never self-match.
+    #[serde(default)]
+    pub owner: String,
+    /// Add liquidity only: a limit order that would trade on arrival is
+    /// rejected or repriced according to the book's `PostOnlyPolicy`
+    #[serde(default)]
+    pub post_only: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PriceLevel {
-    pub price: f64,
+    pub price: Price,
+    /// Displayed quantity only; iceberg reserves are not included
     pub total_amount: i32,
-    pub orders: Vec<Order>,
+    pub orders: VecDeque<Order>,
+}
+
+impl PriceLevel {
+    /// Displayed plus hidden quantity, i.e. everything that can trade here
+    pub fn executable_amount(&self) -> i32 {
+        self.total_amount + self.orders.iter().map(|o| o.hidden_amount).sum::<i32>()
+    }
+
+    /// Called with the ascending queue positions of orders whose displayed
+    /// quantity is used up. Icebergs with reserve left show a new peak and go
+    /// to the back of the queue, keeping their relative order; every other
+    /// order is removed and its id returned.
+    fn remove_exhausted(&mut self, positions: &[usize]) -> Vec<u128> {
+        let mut removed = Vec::new();
+        let mut refilled = Vec::new();
+        for &pos in positions.iter().rev() {
+            let Some(mut order) = self.orders.remove(pos) else {
+                continue;
+            };
+            if order.hidden_amount == 0 {
+                removed.push(order.id);
+                continue;
+            }
+            let refill = order.display_amount.min(order.hidden_amount);
+            order.amount = refill;
+            order.hidden_amount -= refill;
+            self.total_amount += refill;
+            refilled.push(order);
+        }
+        self.orders.extend(refilled.into_iter().rev());
+        removed
+    }
+}
+
+/// Price levels of one side of the book, ordered best price first.
+/// Levels are keyed by a priority key (negated price for bids) so both sides
+/// iterate best-first and lookups, inserts and removals are O(log n).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BookSide {
+    side: Action,
+    levels: BTreeMap<i64, PriceLevel>,
+}
+
+impl BookSide {
+    pub fn new(side: Action) -> Self {
+        Self {
+            side,
+            levels: BTreeMap::new(),
+        }
+    }
+
+    fn key(&self, price: Price) -> i64 {
+        match self.side {
+            Action::Buy => -price.units(),
+            Action::Sell => price.units(),
+        }
+    }
+
+    /// Best price level: highest bid or lowest ask
+    pub fn first(&self) -> Option<&PriceLevel> {
+        self.levels.values().next()
+    }
+
+    pub fn get(&self, price: Price) -> Option<&PriceLevel> {
+        self.levels.get(&self.key(price))
+    }
+
+    /// Levels from best to worst price
+    pub fn iter(&self) -> impl Iterator<Item = &PriceLevel> {
+        self.levels.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels.is_empty()
+    }
+
+    fn level_mut(&mut self, price: Price) -> &mut PriceLevel {
+        let key = self.key(price);
+        self.levels.entry(key).or_insert_with(|| PriceLevel {
+            price,
+            total_amount: 0,
+            orders: VecDeque::new(),
+        })
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct OrderBook {
     pub symbol: String,
-    pub bids: Vec<PriceLevel>,
-    pub asks: Vec<PriceLevel>,
+    pub bids: BookSide,
+    pub asks: BookSide,
     pub last_update: u128,
+    /// Minimum price increment of the instrument traded in this book
+    pub tick_size: Price,
+    pub market_remainder_policy: MarketRemainderPolicy,
+    pub post_only_policy: PostOnlyPolicy,
+    pub self_trade_prevention: SelfTradePrevention,
+    /// How incoming quantity is shared among the orders at a price level
+    #[serde(skip, default = "default_matching_policy")]
+    pub matching_policy: Arc<dyn MatchingPolicy>,
+    /// Static band outside which limit prices are refused
+    pub price_collar: Option<PriceCollar>,
+    /// Largest distance, in basis points, of a limit price or of a market
+    /// order's sweep from the last trade price
+    pub dynamic_collar_bps: Option<i64>,
+    /// Moves the book to an auction when trading would move the price too far
+    pub volatility_guard: Option<VolatilityGuard>,
+    trading_status: TradingStatus,
+    last_trade_price: Option<Price>,
+    /// Time and price of the trades inside the volatility guard's window
+    recent_trades: VecDeque<(u128, Price)>,
+    /// When the auction started by the volatility guard is due to uncross
+    auction_end: Option<u128>,
+    /// Sequence number of the last `BookDelta` produced
+    sequence: u64,
+    /// Levels changed since the last delta, with their displayed quantity
+    /// before the first change (`None` if the level did not exist)
+    #[serde(skip)]
+    touched: Vec<(Action, Price, Option<i32>)>,
+    /// Sequence number of the last `OrderEvent`
+    event_sequence: u64,
+    /// Order events not yet handed out in a report
+    #[serde(skip)]
+    events: Vec<OrderEvent>,
+    /// Untriggered stop and stop-limit orders, in arrival order
+    stops: Vec<Order>,
+    /// Side and price level of every resting order, by order id
+    index: HashMap<u128, OrderLocation>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct BookUpdate {
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+struct OrderLocation {
+    side: Action,
+    price: Price,
+}
+
+/// Live state of a resting order
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct OrderState {
+    pub id: u128,
+    pub side: Action,
+    pub price: Price,
+    /// Open quantity, including any hidden iceberg reserve
+    pub remaining: i32,
+    pub displayed: i32,
+    /// Number of orders ahead of this one at its price level
+    pub queue_position: usize,
+}
+
+/// Outcome of a cancel request against the book
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum CancelResult {
+    /// The order was removed from the book
+    Cancelled { id: u128, cancelled: i32 },
+    /// Only part of the order was cancelled, the rest keeps resting
+    Reduced { id: u128, cancelled: i32, remaining: i32 },
+    /// No resting order with this id
+    NotFound { id: u128 },
+}
+
+/// What to do with the part of a market order the book cannot fill
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum MarketRemainderPolicy {
+    /// Fill what is available and cancel the rest
+    Cancel,
+    /// Refuse the whole order unless it can be filled completely
+    Reject,
+}
+
+/// Inclusive range of limit prices a book accepts
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct PriceCollar {
+    pub min: Price,
+    pub max: Price,
+}
+
+impl PriceCollar {
+    pub fn contains(&self, price: Price) -> bool {
+        self.min <= price && price <= self.max
+    }
+}
+
+/// Volatility interruption settings. A trade that would print more than
+/// `max_move_bps` away from any trade of the last `window` nanoseconds is not
+/// executed; continuous matching stops and the book stays in an auction
+/// until `uncross` is called, which is due `auction_duration` nanoseconds
+/// after the interruption.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct VolatilityGuard {
+    pub max_move_bps: i64,
+    pub window: u128,
+    pub auction_duration: u128,
+}
+
+impl VolatilityGuard {
+    /// Whether a trade at `price` at time `now` breaches the guard
+    fn is_breached(&self, recent_trades: &VecDeque<(u128, Price)>, price: Price, now: u128) -> bool {
+        recent_trades
+            .iter()
+            .filter(|&&(timestamp, _)| timestamp + self.window >= now)
+            .any(|&(_, reference)| exceeds_bps(price, reference, self.max_move_bps))
+    }
+}
+
+/// What to do with a post-only order that would trade on arrival
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum PostOnlyPolicy {
+    Reject,
+    /// Rest one tick behind the best opposite price instead
+    Reprice,
+}
+
+/// How the matching loops handle an incoming order meeting a resting order
+/// from the same owner
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum SelfTradePrevention {
+    /// Same-owner orders trade with each other
+    Allow,
+    /// Cancel the rest of the incoming order
+    CancelNewest,
+    /// Cancel the resting order and keep matching
+    CancelOldest,
+    /// Cancel the resting order and the rest of the incoming order
+    CancelBoth,
+    /// Reduce both orders by the smaller open quantity without trading
+    Decrement,
+}
+
+/// Kind of change to one aggregated price level
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum LevelChange {
+    New,
+    Change,
+    Delete,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LevelDelta {
+    pub side: Action,
+    pub price: Price,
+    /// Displayed quantity after the change; zero when the level was deleted
+    pub amount: i32,
+    pub change: LevelChange,
+}
+
+/// Aggregated level changes made by one book operation. Applying deltas in
+/// sequence order to a snapshot with a lower sequence reproduces the book.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BookDelta {
     pub symbol: String,
-    pub bids: Vec<PriceLevel>,
-    pub asks: Vec<PriceLevel>,
-    pub last_update: u128,
+    pub sequence: u64,
+    pub timestamp: u128,
+    pub levels: Vec<LevelDelta>,
 }
 
-/// Global flag to control order processing
-pub static mut PROCESS_ORDER: bool = true;
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum OrderEventKind {
+    /// Order, or an iceberg refill, joined the back of its level
+    Add,
+    /// Displayed quantity traded; an order executed down to zero leaves the queue
+    Execute,
+    /// Displayed quantity was cancelled and the order kept its place
+    Reduce,
+    /// Order left the book without trading
+    Delete,
+}
+
+/// Order-by-order (L3) change to a resting order. Only displayed quantity is
+/// visible, so hidden iceberg reserves never appear. `queue_position` is the
+/// order's index in its level once all earlier events have been applied.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct OrderEvent {
+    pub sequence: u64,
+    pub kind: OrderEventKind,
+    pub order_id: u128,
+    pub side: Action,
+    pub price: Price,
+    pub amount: i32,
+    pub queue_position: usize,
+    pub timestamp: u128,
+}
+
+/// A single fill between an incoming order and a resting one
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Trade {
+    pub symbol: String,
+    pub aggressor_id: u128,
+    pub passive_id: u128,
+    pub price: Price,
+    pub amount: i32,
+    /// Side of the aggressor
+    pub side: Action,
+    pub timestamp: u128,
+}
+
+impl fmt::Display for Trade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let contra = match self.side {
+            Action::Buy => "Sell",
+            Action::Sell => "Buy",
+        };
+        write!(
+            f,
+            "TRADE: {:?} {} {} @ {:.2} matched with {} order {} for {}",
+            self.side, self.amount, self.symbol, self.price, contra, self.passive_id, self.amount
+        )
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum ExecStatus {
+    /// Rested on the book without trading
+    New,
+    PartiallyFilled,
+    Filled,
+    /// Cancelled by request, or the unfilled part of a market order was dropped
+    Cancelled,
+    /// A resting order was partially cancelled
+    Reduced,
+    /// A resting order was amended without trading
+    Replaced,
+    /// A DAY order was removed at the end of the session
+    Expired,
+    /// A stop order is held off the book until its stop price trades
+    Pending,
+}
+
+/// Why the book refused an order without acting on it
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum RejectReason {
+    /// Quantity is zero or negative. Cancels and replaces may use zero to
+    /// remove the whole order.
+    NonPositiveAmount,
+    /// Limit or stop price is zero or negative
+    InvalidPrice,
+    /// An order with this id is already live in the book
+    DuplicateOrderId,
+    /// Order is for a different instrument than the book trades
+    WrongInstrument,
+    /// Limit price is outside the book's `PriceCollar`
+    PriceOutsideCollar,
+    /// Cancel or replace for an order that is not resting on the book
+    UnknownOrder,
+    /// All-or-none market order or FOK order that the book cannot fill
+    InsufficientLiquidity,
+    /// Post-only order that would have traded, under `PostOnlyPolicy::Reject`
+    PostOnlyWouldCross,
+    /// The book is halted or closed, or is in auction and the order cannot
+    /// take part in one (market, IOC and FOK orders)
+    BookNotOpen(TradingStatus),
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::NonPositiveAmount => write!(f, "amount must be positive"),
+            RejectReason::InvalidPrice => write!(f, "price must be positive"),
+            RejectReason::DuplicateOrderId => write!(f, "duplicate order id"),
+            RejectReason::WrongInstrument => write!(f, "order is for a different instrument"),
+            RejectReason::PriceOutsideCollar => write!(f, "price outside collar"),
+            RejectReason::UnknownOrder => write!(f, "unknown order"),
+            RejectReason::InsufficientLiquidity => write!(f, "insufficient liquidity"),
+            RejectReason::PostOnlyWouldCross => write!(f, "post-only order would cross"),
+            RejectReason::BookNotOpen(status) => write!(f, "book is {:?}", status),
+        }
+    }
+}
+
+impl std::error::Error for RejectReason {}
+
+/// Trading phase of a single book
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum TradingStatus {
+    /// Continuous matching
+    #[default]
+    Open,
+    /// Trading suspended; only cancels are accepted
+    Halted,
+    /// Call auction: limit orders accumulate without matching until `uncross`
+    Auction,
+    /// Outside the session; only cancels are accepted
+    Closed,
+}
+
+/// Emitted whenever a book moves to a different trading status
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct StatusChange {
+    pub symbol: String,
+    pub from: TradingStatus,
+    pub to: TradingStatus,
+    pub timestamp: u128,
+}
+
+/// What happened to an order passed to `OrderBook::add_order`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ExecutionReport {
+    pub order_id: u128,
+    pub status: ExecStatus,
+    pub trades: Vec<Trade>,
+    pub filled: i32,
+    /// Quantity left resting on the book
+    pub resting: i32,
+    /// Quantity removed without trading
+    pub cancelled: i32,
+    pub book_changed: bool,
+    /// Set when this order tripped the volatility guard
+    pub status_change: Option<StatusChange>,
+    /// Resting orders cancelled or reduced by self-trade prevention
+    pub self_trade_cancels: Vec<CancelResult>,
+    /// Stop orders triggered by this order's trades, in execution order
+    pub triggered: Vec<ExecutionReport>,
+    /// Level changes made by this call, including triggered stops. Set only
+    /// on the report returned by `add_order`.
+    pub delta: Option<BookDelta>,
+    /// Order events of this call, including triggered stops. Set only on the
+    /// report returned by `add_order`.
+    pub order_events: Vec<OrderEvent>,
+}
+
+impl ExecutionReport {
+    fn new(order_id: u128, status: ExecStatus) -> Self {
+        Self {
+            order_id,
+            status,
+            trades: Vec::new(),
+            filled: 0,
+            resting: 0,
+            cancelled: 0,
+            book_changed: false,
+            status_change: None,
+            self_trade_cancels: Vec::new(),
+            triggered: Vec::new(),
+            delta: None,
+            order_events: Vec::new(),
+        }
+    }
+
+    /// Trades of this order followed by those of any stops it triggered
+    pub fn all_trades(&self) -> impl Iterator<Item = &Trade> {
+        self.trades
+            .iter()
+            .chain(self.triggered.iter().flat_map(|r| r.trades.iter()))
+    }
+}
+
+/// One cent, used when a book is created without instrument data
+pub const DEFAULT_TICK_SIZE: Price = Price::from_units(10_000);
 
 impl OrderBook {
     pub fn new(symbol: String) -> Self {
+        Self::with_tick_size(symbol, DEFAULT_TICK_SIZE)
+    }
+
+    /// Book for a registry instrument, using its tick size and price protection
+    pub fn for_instrument(instrument: &Instrument) -> Self {
+        Self {
+            price_collar: instrument.price_collar,
+            dynamic_collar_bps: instrument.dynamic_collar_bps,
+            volatility_guard: instrument.volatility_guard,
+            ..Self::with_tick_size(instrument.symbol.clone(), instrument.tick_size)
+        }
+    }
+
+    pub fn with_tick_size(symbol: String, tick_size: Price) -> Self {
         Self {
             symbol,
-            bids: Vec::new(),
-            asks: Vec::new(),
+            bids: BookSide::new(Action::Buy),
+            asks: BookSide::new(Action::Sell),
             last_update: 0,
+            tick_size,
+            market_remainder_policy: MarketRemainderPolicy::Cancel,
+            post_only_policy: PostOnlyPolicy::Reject,
+            self_trade_prevention: SelfTradePrevention::Allow,
+            matching_policy: default_matching_policy(),
+            price_collar: None,
+            dynamic_collar_bps: None,
+            volatility_guard: None,
+            trading_status: TradingStatus::Open,
+            last_trade_price: None,
+            recent_trades: VecDeque::new(),
+            auction_end: None,
+            sequence: 0,
+            touched: Vec::new(),
+            event_sequence: 0,
+            events: Vec::new(),
+            stops: Vec::new(),
+            index: HashMap::new(),
         }
     }
 
-    pub fn add_order(&mut self, order: &Order) -> bool {
-        unsafe {
-            if !PROCESS_ORDER {
-                println!("{} ", self.symbol);
-                return false;
+    /// Checks `order` against the book and executes it. A refused order
+    /// leaves the book untouched and comes back as the `Err` reason.
+    pub fn add_order(&mut self, order: &Order) -> Result<ExecutionReport, RejectReason> {
+        let (touched, events) = (self.touched.len(), self.events.len());
+        let mut report = match self.check_order(order).and_then(|()| self.execute(order)) {
+            Ok(report) => report,
+            Err(reason) => {
+                // Nothing changed, so nothing recorded for this order may reach the next delta
+                self.touched.truncate(touched);
+                self.event_sequence -= (self.events.len() - events) as u64;
+                self.events.truncate(events);
+                return Err(reason);
             }
+        };
+        let is_stop = matches!(order.order_type, OrderType::Stop | OrderType::StopLimit);
+        if !report.trades.is_empty() || is_stop {
+            report.triggered = self.trigger_stops(order.timestamp);
         }
+        report.delta = self.finish_delta(order.timestamp);
+        report.order_events = self.take_events(order.timestamp);
+        // Only continuous trading matches; a book in auction may be crossed,
+        // and so may one halted or closed before its auction uncrossed
+        debug_assert!(
+            self.trading_status != TradingStatus::Open || !self.is_crossed(),
+            "{} book crossed after order {}",
+            self.symbol,
+            order.id
+        );
+        Ok(report)
+    }
 
-        let price_levels = match order.action {
+    /// Sequence number of the last `BookDelta`, also carried by snapshots
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Aggregated top `depth` levels of each side, best price first
+    pub fn snapshot(&self, depth: usize) -> proto::BookUpdate {
+        let levels = |side: &BookSide| {
+            side.iter()
+                .take(depth)
+                .map(|level| proto::PriceLevel {
+                    price: level.price.to_f64(),
+                    amount: level.total_amount,
+                })
+                .collect()
+        };
+        proto::BookUpdate {
+            symbol: self.symbol.clone(),
+            bids: levels(&self.bids),
+            asks: levels(&self.asks),
+            timestamp: self.last_update as u64,
+            sequence: self.sequence,
+        }
+    }
+
+    /// Turns the levels touched since the last delta into a `BookDelta` with
+    /// the next sequence number. Changes made through the public helpers such
+    /// as `cancel_order` are folded into the next delta. Returns `None` when
+    /// no level ended up different.
+    fn finish_delta(&mut self, timestamp: u128) -> Option<BookDelta> {
+        let touched = std::mem::take(&mut self.touched);
+        let levels: Vec<LevelDelta> = touched
+            .into_iter()
+            .filter_map(|(side, price, before)| {
+                let book_side = match side {
+                    Action::Buy => &self.bids,
+                    Action::Sell => &self.asks,
+                };
+                let after = book_side.get(price).map(|l| l.total_amount);
+                let change = match (before, after) {
+                    (None, Some(_)) => LevelChange::New,
+                    (Some(_), None) => LevelChange::Delete,
+                    (Some(b), Some(a)) if b != a => LevelChange::Change,
+                    _ => return None,
+                };
+                Some(LevelDelta {
+                    side,
+                    price,
+                    amount: after.unwrap_or(0),
+                    change,
+                })
+            })
+            .collect();
+        if levels.is_empty() {
+            return None;
+        }
+        self.sequence += 1;
+        Some(BookDelta {
+            symbol: self.symbol.clone(),
+            sequence: self.sequence,
+            timestamp,
+            levels,
+        })
+    }
+
+    /// Hands out the order events recorded since the last call, stamped with `timestamp`
+    fn take_events(&mut self, timestamp: u128) -> Vec<OrderEvent> {
+        let mut events = std::mem::take(&mut self.events);
+        for event in &mut events {
+            event.timestamp = timestamp;
+        }
+        events
+    }
+
+    /// Band of `dynamic_collar_bps` around the last trade price, if both exist
+    pub fn dynamic_collar(&self) -> Option<PriceCollar> {
+        let bps = self.dynamic_collar_bps?;
+        let reference = self.last_trade_price?;
+        let offset = Price::from_units((reference.units() as i128 * bps as i128 / 10_000) as i64);
+        Some(PriceCollar {
+            min: reference - offset,
+            max: reference + offset,
+        })
+    }
+
+    /// Static checks that do not depend on the opposite side of the book
+    fn check_order(&self, order: &Order) -> Result<(), RejectReason> {
+        if order.instrument != self.symbol {
+            return Err(RejectReason::WrongInstrument);
+        }
+        let amends = matches!(order.order_type, OrderType::Cancel | OrderType::Replace);
+        if order.amount < 0 || (order.amount == 0 && !amends) {
+            return Err(RejectReason::NonPositiveAmount);
+        }
+        // A zero-quantity replace is a cancel, so its price is never used
+        let limit_priced = match order.order_type {
+            OrderType::Limit | OrderType::StopLimit => true,
+            OrderType::Replace => order.amount > 0,
+            _ => false,
+        };
+        let stop_priced = matches!(order.order_type, OrderType::Stop | OrderType::StopLimit);
+        if (limit_priced && order.price <= Price::ZERO) || (stop_priced && order.stop_price <= Price::ZERO) {
+            return Err(RejectReason::InvalidPrice);
+        }
+        if limit_priced
+            && (self.price_collar.is_some_and(|c| !c.contains(order.price))
+                || self.dynamic_collar().is_some_and(|c| !c.contains(order.price)))
+        {
+            return Err(RejectReason::PriceOutsideCollar);
+        }
+        let is_new = !amends;
+        if is_new && (self.index.contains_key(&order.id) || self.stops.iter().any(|s| s.id == order.id)) {
+            return Err(RejectReason::DuplicateOrderId);
+        }
+        Ok(())
+    }
+
+    pub fn trading_status(&self) -> TradingStatus {
+        self.trading_status
+    }
+
+    /// When the auction the volatility guard started is due to uncross, or
+    /// `None` if the book is not in such an auction
+    pub fn auction_end(&self) -> Option<u128> {
+        self.auction_end
+    }
+
+    /// Moves the book to `status`, returning the change event, or `None` if
+    /// the book was already in that status. A crossed book cannot reopen this
+    /// way and keeps its status; only `uncross` takes it back to `Open`.
+    pub fn set_trading_status(&mut self, status: TradingStatus, timestamp: u128) -> Option<StatusChange> {
+        if status == self.trading_status || (status == TradingStatus::Open && self.is_crossed()) {
+            return None;
+        }
+        let change = StatusChange {
+            symbol: self.symbol.clone(),
+            from: self.trading_status,
+            to: status,
+            timestamp,
+        };
+        self.trading_status = status;
+        self.auction_end = None;
+        Some(change)
+    }
+
+    fn execute(&mut self, order: &Order) -> Result<ExecutionReport, RejectReason> {
+        // Cancels are always honoured so participants can pull orders during a halt
+        let accepted = match self.trading_status {
+            TradingStatus::Open => true,
+            TradingStatus::Auction => {
+                !matches!(order.order_type, OrderType::Market)
+                    && !matches!(order.time_in_force, TimeInForce::Ioc | TimeInForce::Fok)
+            }
+            TradingStatus::Halted | TradingStatus::Closed => order.order_type == OrderType::Cancel,
+        };
+        if !accepted {
+            return Err(RejectReason::BookNotOpen(self.trading_status));
+        }
+
+        let report = match order.order_type {
+            OrderType::Market => self.execute_market_order(order)?,
+            OrderType::Limit => self.execute_limit_order(order)?,
+            OrderType::Cancel => self.execute_cancel(order)?,
+            OrderType::Replace => self.replace_order(order)?,
+            OrderType::Stop | OrderType::StopLimit => {
+                self.stops.push(order.clone());
+                ExecutionReport::new(order.id, ExecStatus::Pending)
+            }
+        };
+        if report.book_changed {
+            self.last_update = order.timestamp;
+        }
+        Ok(report)
+    }
+
+    /// Price a limit order enters the book at once post-only handling is
+    /// applied, or why the book refuses it. Only the opposite side is looked
+    /// at, so a replacement can be checked before the order it replaces is
+    /// pulled.
+    fn limit_entry_price(&self, order: &Order) -> Result<Price, RejectReason> {
+        let mut price = order.price;
+        if order.post_only && self.trading_status == TradingStatus::Open {
+            let contra = match order.action {
+                Action::Buy => self.best_ask(),
+                Action::Sell => self.best_bid(),
+            };
+            if let Some(contra_price) = contra.map(|l| l.price).filter(|&p| crosses(order.action, p, Some(price))) {
+                if self.post_only_policy == PostOnlyPolicy::Reject {
+                    return Err(RejectReason::PostOnlyWouldCross);
+                }
+                price = match order.action {
+                    Action::Buy => contra_price - self.tick_size,
+                    Action::Sell => contra_price + self.tick_size,
+                };
+                if price <= Price::ZERO {
+                    return Err(RejectReason::PostOnlyWouldCross);
+                }
+            }
+        }
+        if order.time_in_force == TimeInForce::Fok && !self.can_fill(order, Some(price)) {
+            return Err(RejectReason::InsufficientLiquidity);
+        }
+        Ok(price)
+    }
+
+    fn execute_limit_order(&mut self, order: &Order) -> Result<ExecutionReport, RejectReason> {
+        let price = self.limit_entry_price(order)?;
+        let repriced;
+        let order = if price != order.price {
+            repriced = Order { price, ..order.clone() };
+            &repriced
+        } else {
+            order
+        };
+
+        let matched = if self.trading_status == TradingStatus::Auction {
+            Matched {
+                remaining: order.amount,
+                ..Matched::default()
+            }
+        } else {
+            self.match_opposite(order, Some(order.price))
+        };
+        let Matched { mut remaining, trades, self_trade_cancelled, self_trade_cancels, status_change } = matched;
+        let filled = order.amount - remaining - self_trade_cancelled;
+        let mut cancelled = self_trade_cancelled;
+        // A FOK order only comes short if the sweep was interrupted; it never rests
+        if matches!(order.time_in_force, TimeInForce::Ioc | TimeInForce::Fok) {
+            cancelled += remaining;
+            remaining = 0;
+        }
+        if remaining > 0 {
+            self.rest_order(order, remaining);
+        }
+
+        let status = if remaining == 0 && cancelled == 0 {
+            ExecStatus::Filled
+        } else if remaining == 0 {
+            ExecStatus::Cancelled
+        } else if filled > 0 {
+            ExecStatus::PartiallyFilled
+        } else {
+            ExecStatus::New
+        };
+        Ok(ExecutionReport {
+            book_changed: remaining > 0 || !trades.is_empty() || !self_trade_cancels.is_empty(),
+            trades,
+            filled,
+            resting: remaining,
+            cancelled,
+            status_change,
+            self_trade_cancels,
+            ..ExecutionReport::new(order.id, status)
+        })
+    }
+
+    /// Releases stops whose trigger the last trade price has reached and
+    /// executes them, one at a time, as market or limit orders. Trades from a
+    /// triggered order can trigger further stops; each round picks the
+    /// earliest-arrived triggered stop so cascades are deterministic. Released
+    /// orders carry `timestamp`, the time of the trade that triggered them. A
+    /// triggered order the book refuses was already accepted as a stop, so it
+    /// is reported as cancelled rather than rejected.
+    fn trigger_stops(&mut self, timestamp: u128) -> Vec<ExecutionReport> {
+        let mut reports = Vec::new();
+        while self.trading_status == TradingStatus::Open {
+            let last_trade_price = self.last_trade_price;
+            let Some(idx) = self.stops.iter().position(|s| stop_triggered(s, last_trade_price)) else {
+                break;
+            };
+            let stop = self.stops.remove(idx);
+            let order_type = match stop.order_type {
+                OrderType::Stop => OrderType::Market,
+                _ => OrderType::Limit,
+            };
+            let report = self.execute(&Order { order_type, timestamp, ..stop }).unwrap_or_else(|_| ExecutionReport {
+                cancelled: stop.amount,
+                ..ExecutionReport::new(stop.id, ExecStatus::Cancelled)
+            });
+            reports.push(report);
+        }
+        reports
+    }
+
+    /// Ends the trading session: every resting or pending DAY order expires.
+    pub fn end_session(&mut self) -> Vec<ExecutionReport> {
+        let day_orders: Vec<u128> = self
+            .bids
+            .iter()
+            .chain(self.asks.iter())
+            .flat_map(|level| level.orders.iter())
+            .chain(self.stops.iter())
+            .filter(|o| o.time_in_force == TimeInForce::Day)
+            .map(|o| o.id)
+            .collect();
+        day_orders
+            .into_iter()
+            .filter_map(|id| match self.cancel_order(id, 0) {
+                CancelResult::Cancelled { id, cancelled } => Some(ExecutionReport {
+                    cancelled,
+                    book_changed: true,
+                    delta: self.finish_delta(self.last_update),
+                    order_events: self.take_events(self.last_update),
+                    ..ExecutionReport::new(id, ExecStatus::Expired)
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn execute_cancel(&mut self, order: &Order) -> Result<ExecutionReport, RejectReason> {
+        let report = match self.cancel_order(order.id, order.amount) {
+            CancelResult::Cancelled { id, cancelled } => ExecutionReport {
+                cancelled,
+                book_changed: true,
+                ..ExecutionReport::new(id, ExecStatus::Cancelled)
+            },
+            CancelResult::Reduced { id, cancelled, remaining } => ExecutionReport {
+                cancelled,
+                resting: remaining,
+                book_changed: true,
+                ..ExecutionReport::new(id, ExecStatus::Reduced)
+            },
+            CancelResult::NotFound { .. } => return Err(RejectReason::UnknownOrder),
+        };
+        Ok(report)
+    }
+
+    /// Amends the resting order `order.id` to `order.price` and `order.amount`,
+    /// where `amount` is the new open quantity and zero cancels the order.
+    /// Reducing the quantity at the same price keeps queue priority. A price
+    /// change or a quantity increase re-enters the order at the back of the
+    /// queue, trading first if the new price crosses. Only price and quantity
+    /// are taken from the amend; side, instrument, time in force, display
+    /// size, owner and post-only stay as the order was entered. Pending stops are not on the book
+    /// and cannot be amended; cancel and re-enter them instead.
+    fn replace_order(&mut self, order: &Order) -> Result<ExecutionReport, RejectReason> {
+        if order.amount == 0 {
+            return self.execute_cancel(order);
+        }
+        let (_, resting) = self.find_order(order.id).ok_or(RejectReason::UnknownOrder)?;
+        let remaining = resting.amount + resting.hidden_amount;
+        if order.price == resting.price && order.amount <= remaining {
+            let reduce_by = remaining - order.amount;
+            if reduce_by > 0 {
+                self.cancel_order(order.id, reduce_by);
+            }
+            return Ok(ExecutionReport {
+                resting: order.amount,
+                cancelled: reduce_by,
+                book_changed: reduce_by > 0,
+                ..ExecutionReport::new(order.id, ExecStatus::Replaced)
+            });
+        }
+
+        let replacement = Order {
+            price: order.price,
+            amount: order.amount,
+            order_type: OrderType::Limit,
+            timestamp: order.timestamp,
+            hidden_amount: 0,
+            ..resting.clone()
+        };
+        // Refuse before pulling the original so a refused amend leaves it resting
+        self.limit_entry_price(&replacement)?;
+        self.cancel_order(order.id, 0);
+        let mut report = self.execute_limit_order(&replacement)?;
+        if report.status == ExecStatus::New {
+            report.status = ExecStatus::Replaced;
+        }
+        Ok(report)
+    }
+
+    /// Price of the most recent trade, used as the auction reference price
+    pub fn last_trade_price(&self) -> Option<Price> {
+        self.last_trade_price
+    }
+
+    pub fn best_bid(&self) -> Option<&PriceLevel> {
+        self.bids.first()
+    }
+
+    pub fn best_ask(&self) -> Option<&PriceLevel> {
+        self.asks.first()
+    }
+
+    /// True when the best bid is at or above the best ask. Matching keeps the
+    /// book uncrossed, so this only holds if an invariant was broken.
+    pub fn is_crossed(&self) -> bool {
+        match (self.bids.first(), self.asks.first()) {
+            (Some(bid), Some(ask)) => bid.price >= ask.price,
+            _ => false,
+        }
+    }
+
+    /// Inserts the unfilled `amount` of `order` at the back of its price level.
+    fn rest_order(&mut self, order: &Order, amount: i32) {
+        let side = match order.action {
             Action::Buy => &mut self.bids,
             Action::Sell => &mut self.asks,
         };
 
-        let price_key = (order.price * 1_000_000.0) as i64;
-
-        let price_level = price_levels
-            .iter_mut()
-            .find(|pl| (pl.price * 1_000_000.0) as i64 == price_key);
-        match price_level {
-            Some(pl) => {
-                pl.orders.push(order.clone());
-                pl.total_amount += order.amount;
-            }
-            None => {
-                price_levels.push(PriceLevel {
-                    price: order.price,
-                    total_amount: order.amount,
-                    orders: vec![order.clone()],
-                });
-            }
-        }
-
-        self.bids
-            .sort_by(|a, b| b.price.partial_cmp(&a.price).unwrap());
-        self.asks
-            .sort_by(|a, b| a.price.partial_cmp(&b.price).unwrap());
-
-        let mut book_changed = false;
-        let (crossed, trades) = match order.action {
-            Action::Buy => {
-                let mut remaining = order.amount;
-                let mut trades = Vec::new();
-                let mut to_remove = Vec::new();
-
-                for ask in &mut self.asks {
-                    if ask.price <= order.price && remaining > 0 {
-                        let mut matched_orders = Vec::new();
-                        let mut ask_remaining = ask.total_amount;
-                        let mut i = 0;
-                        while i < ask.orders.len() && remaining > 0 {
-                            let ask_order = &mut ask.orders[i];
-                            let fill = remaining.min(ask_order.amount);
-                            trades.push(format!(
-                                "TRADE: Buy {} {} @ {:.2} matched with Sell order {} for {}",
-                                fill, self.symbol, ask.price, ask_order.id, fill
-                            ));
-                            ask_order.amount -= fill;
-                            ask_remaining -= fill;
-                            remaining -= fill;
-                            if ask_order.amount == 0 {
-                                matched_orders.push(i);
-                            } else {
-                                i += 1;
-                            }
-                        }
-                        for &idx in matched_orders.iter().rev() {
-                            ask.orders.remove(idx);
-                        }
-                        ask.total_amount = ask_remaining;
-                        if ask.orders.is_empty() {
-                            to_remove.push(ask.price);
-                        }
-                        if remaining == 0 {
-                            break;
-                        }
+        let mut resting = order.clone();
+        resting.amount = amount;
+        resting.hidden_amount = 0;
+        if order.display_amount > 0 && amount > order.display_amount {
+            resting.amount = order.display_amount;
+            resting.hidden_amount = amount - order.display_amount;
+        }
+        touch(&mut self.touched, order.action, order.price, side.get(order.price).map(|l| l.total_amount));
+        let level = side.level_mut(order.price);
+        level.total_amount += resting.amount;
+        let event = (OrderEventKind::Add, resting.amount, level.orders.len());
+        level.orders.push_back(resting);
+        record(&mut self.events, &mut self.event_sequence, event, order);
+        self.index.insert(
+            order.id,
+            OrderLocation {
+                side: order.action,
+                price: order.price,
+            },
+        );
+    }
+
+    /// Looks up a resting order by id. Finding the level is O(1); the queue
+    /// position is found by scanning that level only.
+    pub fn get_order(&self, id: u128) -> Option<OrderState> {
+        let (queue_position, order) = self.find_order(id)?;
+        Some(OrderState {
+            id,
+            side: order.action,
+            price: order.price,
+            remaining: order.amount + order.hidden_amount,
+            displayed: order.amount,
+            queue_position,
+        })
+    }
+
+    /// Queue position and contents of the resting order `id`
+    fn find_order(&self, id: u128) -> Option<(usize, &Order)> {
+        let location = self.index.get(&id)?;
+        let side = match location.side {
+            Action::Buy => &self.bids,
+            Action::Sell => &self.asks,
+        };
+        let level = side.get(location.price)?;
+        level.orders.iter().enumerate().find(|(_, o)| o.id == id)
+    }
+
+    /// Sweeps a market order through the opposite side, no further than the
+    /// dynamic collar if one is set. Market orders never rest; whatever cannot
+    /// be filled is dropped, or the whole order is refused up front under
+    /// `MarketRemainderPolicy::Reject` or when the order is FOK.
+    fn execute_market_order(&mut self, order: &Order) -> Result<ExecutionReport, RejectReason> {
+        let all_or_none = self.market_remainder_policy == MarketRemainderPolicy::Reject
+            || order.time_in_force == TimeInForce::Fok;
+        let limit = self.dynamic_collar().map(|c| match order.action {
+            Action::Buy => c.max,
+            Action::Sell => c.min,
+        });
+        if all_or_none && !self.can_fill(order, limit) {
+            return Err(RejectReason::InsufficientLiquidity);
+        }
+
+        let Matched { remaining, trades, self_trade_cancelled, self_trade_cancels, status_change } =
+            self.match_opposite(order, limit);
+        let cancelled = remaining + self_trade_cancelled;
+        let status = if cancelled == 0 {
+            ExecStatus::Filled
+        } else {
+            ExecStatus::Cancelled
+        };
+        Ok(ExecutionReport {
+            book_changed: !trades.is_empty() || !self_trade_cancels.is_empty(),
+            trades,
+            filled: order.amount - cancelled,
+            cancelled,
+            status_change,
+            self_trade_cancels,
+            ..ExecutionReport::new(order.id, status)
+        })
+    }
+
+    /// Whether `order` would fill completely against the opposite side at or
+    /// better than `limit`. Only walks as many levels as a sweep of that size
+    /// would. Under self-trade prevention, same-owner orders are skipped when
+    /// they would be cancelled and otherwise end the sweep. A level the
+    /// volatility guard would stop the sweep at also ends it.
+    fn can_fill(&self, order: &Order, limit: Option<Price>) -> bool {
+        let contra = match order.action {
+            Action::Buy => &self.asks,
+            Action::Sell => &self.bids,
+        };
+        let mut available = 0;
+        let mut swept = Vec::new();
+        for level in contra.iter() {
+            if !crosses(order.action, level.price, limit) {
+                break;
+            }
+            if let Some(guard) = self.volatility_guard {
+                if guard.is_breached(&self.recent_trades, level.price, order.timestamp)
+                    || swept.iter().any(|&p| exceeds_bps(level.price, p, guard.max_move_bps))
+                {
+                    return false;
+                }
+                swept.push(level.price);
+            }
+            for resting in &level.orders {
+                if is_self_trade(self.self_trade_prevention, order, resting) {
+                    if self.self_trade_prevention == SelfTradePrevention::CancelOldest {
+                        continue;
                     }
+                    return false;
                 }
-                self.asks.retain(|a| !to_remove.contains(&a.price));
-                if !trades.is_empty() {
-                    book_changed = true;
+                available += resting.amount + resting.hidden_amount;
+                if available >= order.amount {
+                    return true;
                 }
-                (remaining < order.amount, trades)
-            }
-            Action::Sell => {
-                let mut remaining = order.amount;
-                let mut trades = Vec::new();
-                let mut to_remove = Vec::new();
-
-                for bid in &mut self.bids {
-                    if bid.price >= order.price && remaining > 0 {
-                        let mut matched_orders = Vec::new();
-                        let mut bid_remaining = bid.total_amount;
-                        let mut i = 0;
-                        while i < bid.orders.len() && remaining > 0 {
-                            let bid_order = &mut bid.orders[i];
-                            let fill = remaining.min(bid_order.amount);
-                            trades.push(format!(
-                                "TRADE: Sell {} {} @ {:.2} matched with Buy order {} for {}",
-                                fill, self.symbol, bid.price, bid_order.id, fill
-                            ));
-                            bid_order.amount -= fill;
-                            bid_remaining -= fill;
-                            remaining -= fill;
-                            if bid_order.amount == 0 {
-                                matched_orders.push(i);
-                            } else {
-                                i += 1;
-                            }
-                        }
-                        for &idx in matched_orders.iter().rev() {
-                            bid.orders.remove(idx);
-                        }
-                        bid.total_amount = bid_remaining;
-                        if bid.orders.is_empty() {
-                            to_remove.push(bid.price);
+            }
+        }
+        false
+    }
+
+    /// Matches `order` against the opposite side, best price first, stopping at
+    /// `limit` if one is given. Each level is shared out by the book's
+    /// `MatchingPolicy`. Same-owner orders are handled according to the book's
+    /// `SelfTradePrevention` mode instead of trading once the allocation
+    /// reaches them in queue order. Matching stops before any level that
+    /// would breach the volatility guard and the book moves to an auction.
+    fn match_opposite(&mut self, order: &Order, limit: Option<Price>) -> Matched {
+        let stp = self.self_trade_prevention;
+        let check_stp = stp != SelfTradePrevention::Allow && !order.owner.is_empty();
+        let contra = match order.action {
+            Action::Buy => &mut self.asks,
+            Action::Sell => &mut self.bids,
+        };
+
+        let mut matched = Matched {
+            remaining: order.amount,
+            ..Matched::default()
+        };
+        let remaining = &mut matched.remaining;
+        let mut interrupted = false;
+
+        'levels: while *remaining > 0 {
+            let Some(mut entry) = contra.levels.first_entry() else {
+                break;
+            };
+            let level = entry.get_mut();
+            if !crosses(order.action, level.price, limit) {
+                break;
+            }
+            touch(&mut self.touched, contra.side, level.price, Some(level.total_amount));
+            if let Some(guard) = self.volatility_guard {
+                if guard.is_breached(&self.recent_trades, level.price, order.timestamp) {
+                    interrupted = true;
+                    break;
+                }
+            }
+            while *remaining > 0 && !level.orders.is_empty() {
+                // Orders queued ahead of the first same-owner order are allocated first
+                let eligible = if check_stp {
+                    let own = level.orders.iter().position(|o| is_self_trade(stp, order, o));
+                    own.unwrap_or(level.orders.len())
+                } else {
+                    level.orders.len()
+                };
+
+                if eligible == 0 {
+                    let resting = level.orders.front_mut().unwrap();
+                    if matches!(stp, SelfTradePrevention::CancelNewest | SelfTradePrevention::CancelBoth) {
+                        matched.self_trade_cancelled += *remaining;
+                        *remaining = 0;
+                    }
+                    let open = resting.amount + resting.hidden_amount;
+                    let reduce_by = match stp {
+                        SelfTradePrevention::CancelOldest | SelfTradePrevention::CancelBoth => open,
+                        SelfTradePrevention::Decrement => {
+                            let reduce_by = (*remaining).min(open);
+                            *remaining -= reduce_by;
+                            matched.self_trade_cancelled += reduce_by;
+                            reduce_by
                         }
-                        if remaining == 0 {
-                            break;
+                        _ => 0,
+                    };
+                    if reduce_by == 0 {
+                        break;
+                    }
+                    if reduce_by < open {
+                        // Same as a partial cancel: the hidden reserve goes first
+                        let from_hidden = reduce_by.min(resting.hidden_amount);
+                        resting.hidden_amount -= from_hidden;
+                        resting.amount -= reduce_by - from_hidden;
+                        level.total_amount -= reduce_by - from_hidden;
+                        if reduce_by > from_hidden {
+                            let event = (OrderEventKind::Reduce, reduce_by - from_hidden, 0);
+                            record(&mut self.events, &mut self.event_sequence, event, resting);
                         }
+                        matched.self_trade_cancels.push(CancelResult::Reduced {
+                            id: resting.id,
+                            cancelled: reduce_by,
+                            remaining: open - reduce_by,
+                        });
+                    } else {
+                        let removed = level.orders.pop_front().unwrap();
+                        level.total_amount -= removed.amount;
+                        self.index.remove(&removed.id);
+                        let event = (OrderEventKind::Delete, removed.amount, 0);
+                        record(&mut self.events, &mut self.event_sequence, event, &removed);
+                        matched.self_trade_cancels.push(CancelResult::Cancelled {
+                            id: removed.id,
+                            cancelled: open,
+                        });
+                    }
+                    continue;
+                }
+
+                let allocations = self
+                    .matching_policy
+                    .allocate(*remaining, &mut level.orders.iter().take(eligible).map(|o| o.amount));
+                let mut exhausted = Vec::new();
+                let mut allocated = 0;
+                for (idx, fill) in allocations {
+                    let resting = &mut level.orders[idx];
+                    matched.trades.push(Trade {
+                        symbol: self.symbol.clone(),
+                        aggressor_id: order.id,
+                        passive_id: resting.id,
+                        price: level.price,
+                        amount: fill,
+                        side: order.action,
+                        timestamp: order.timestamp,
+                    });
+                    resting.amount -= fill;
+                    allocated += fill;
+                    // Orders executed to zero earlier in this round have left the queue
+                    let position = idx - exhausted.iter().filter(|&&e| e < idx).count();
+                    let event = (OrderEventKind::Execute, fill, position);
+                    record(&mut self.events, &mut self.event_sequence, event, resting);
+                    if resting.amount == 0 {
+                        exhausted.push(idx);
                     }
                 }
-                self.bids.retain(|b| !to_remove.contains(&b.price));
-                if !trades.is_empty() {
-                    book_changed = true;
+                debug_assert!(allocated > 0, "matching policy allocated nothing");
+                if allocated == 0 {
+                    break 'levels;
+                }
+                level.total_amount -= allocated;
+                *remaining -= allocated;
+                if self.volatility_guard.is_some() {
+                    self.recent_trades.push_back((order.timestamp, level.price));
                 }
-                (remaining < order.amount, trades)
+                let removed = level.remove_exhausted(&exhausted);
+                let refilled = exhausted.len() - removed.len();
+                for id in removed {
+                    self.index.remove(&id);
+                }
+                record_refills(&mut self.events, &mut self.event_sequence, level, refilled);
+            }
+            if level.orders.is_empty() {
+                entry.remove();
             }
+        }
+        if let Some(trade) = matched.trades.last() {
+            self.last_trade_price = Some(trade.price);
+        }
+        if let Some(guard) = self.volatility_guard {
+            while self.recent_trades.front().is_some_and(|&(t, _)| t + guard.window < order.timestamp) {
+                self.recent_trades.pop_front();
+            }
+        }
+        if interrupted {
+            matched.status_change = self.set_trading_status(TradingStatus::Auction, order.timestamp);
+            if let (Some(_), Some(guard)) = (&matched.status_change, self.volatility_guard) {
+                self.auction_end = Some(order.timestamp + guard.auction_duration);
+            }
+        }
+        matched
+    }
+
+    /// Cancels `amount` of the resting order `id`, or all of it when `amount`
+    /// is zero or covers the whole remaining quantity. The level is found in
+    /// O(1); finding the order in its queue is O(orders at that price).
+    pub fn cancel_order(&mut self, id: u128, amount: i32) -> CancelResult {
+        let Some(&location) = self.index.get(&id) else {
+            return self.cancel_stop(id, amount);
         };
+        let side = match location.side {
+            Action::Buy => &mut self.bids,
+            Action::Sell => &mut self.asks,
+        };
+        let key = side.key(location.price);
+        let level = side
+            .levels
+            .get_mut(&key)
+            .expect("indexed order must have a price level");
+        let order_idx = level
+            .orders
+            .iter()
+            .position(|o| o.id == id)
+            .expect("indexed order must be queued at its level");
+        touch(&mut self.touched, location.side, location.price, Some(level.total_amount));
 
-        if crossed {
-            for trade in trades {
-                println!("{}", trade);
+        // Partial cancels of an iceberg come out of the hidden reserve first
+        let resting = &mut level.orders[order_idx];
+        if amount > 0 && amount < resting.amount + resting.hidden_amount {
+            let from_hidden = amount.min(resting.hidden_amount);
+            resting.hidden_amount -= from_hidden;
+            resting.amount -= amount - from_hidden;
+            level.total_amount -= amount - from_hidden;
+            if amount > from_hidden {
+                let event = (OrderEventKind::Reduce, amount - from_hidden, order_idx);
+                record(&mut self.events, &mut self.event_sequence, event, resting);
             }
+            return CancelResult::Reduced {
+                id,
+                cancelled: amount,
+                remaining: resting.amount + resting.hidden_amount,
+            };
+        }
+
+        let removed = level.orders.remove(order_idx).unwrap();
+        level.total_amount -= removed.amount;
+        if level.orders.is_empty() {
+            side.levels.remove(&key);
+        }
+        let event = (OrderEventKind::Delete, removed.amount, order_idx);
+        record(&mut self.events, &mut self.event_sequence, event, &removed);
+        self.index.remove(&id);
+        CancelResult::Cancelled {
+            id,
+            cancelled: removed.amount + removed.hidden_amount,
         }
-        self.last_update = order.timestamp;
-        book_changed
     }
 
-    pub fn get_book_update(&self) -> &Self {
-        self
+    fn cancel_stop(&mut self, id: u128, amount: i32) -> CancelResult {
+        let Some(idx) = self.stops.iter().position(|s| s.id == id) else {
+            return CancelResult::NotFound { id };
+        };
+        let stop = &mut self.stops[idx];
+        if amount > 0 && amount < stop.amount {
+            stop.amount -= amount;
+            return CancelResult::Reduced {
+                id,
+                cancelled: amount,
+                remaining: stop.amount,
+            };
+        }
+        let removed = self.stops.remove(idx);
+        CancelResult::Cancelled {
+            id,
+            cancelled: removed.amount,
+        }
+    }
+}
+
+/// Result of sweeping an incoming order through the opposite side
+#[derive(Default)]
+struct Matched {
+    /// Quantity neither filled nor cancelled by self-trade prevention
+    remaining: i32,
+    trades: Vec<Trade>,
+    /// Incoming quantity cancelled by self-trade prevention
+    self_trade_cancelled: i32,
+    self_trade_cancels: Vec<CancelResult>,
+    /// Set when the volatility guard moved the book to an auction
+    status_change: Option<StatusChange>,
+}
+
+/// Whether `price` is more than `bps` basis points away from `reference`
+fn exceeds_bps(price: Price, reference: Price, bps: i64) -> bool {
+    let distance = (price - reference).abs().units() as i128;
+    distance * 10_000 > reference.units() as i128 * bps as i128
+}
+
+/// Records the quantity a level had before its first change since the last delta
+fn touch(touched: &mut Vec<(Action, Price, Option<i32>)>, side: Action, price: Price, before: Option<i32>) {
+    if !touched.iter().any(|&(s, p, _)| s == side && p == price) {
+        touched.push((side, price, before));
+    }
+}
+
+/// Appends an order event of `(kind, amount, queue_position)` for `order`.
+/// The timestamp is filled in by `take_events`.
+fn record(
+    events: &mut Vec<OrderEvent>,
+    sequence: &mut u64,
+    (kind, amount, queue_position): (OrderEventKind, i32, usize),
+    order: &Order,
+) {
+    *sequence += 1;
+    events.push(OrderEvent {
+        sequence: *sequence,
+        kind,
+        order_id: order.id,
+        side: order.action,
+        price: order.price,
+        amount,
+        queue_position,
+        timestamp: 0,
+    });
+}
+
+/// Records an `Add` for each of the last `refilled` orders of `level`, the
+/// icebergs `remove_exhausted` moved to the back
+fn record_refills(
+    events: &mut Vec<OrderEvent>,
+    sequence: &mut u64,
+    level: &PriceLevel,
+    refilled: usize,
+) {
+    let start = level.orders.len() - refilled;
+    for (position, order) in level.orders.iter().enumerate().skip(start) {
+        record(events, sequence, (OrderEventKind::Add, order.amount, position), order);
+    }
+}
+
+fn default_matching_policy() -> Arc<dyn MatchingPolicy> {
+    Arc::new(Fifo)
+}
+
+fn is_self_trade(stp: SelfTradePrevention, incoming: &Order, resting: &Order) -> bool {
+    stp != SelfTradePrevention::Allow && !incoming.owner.is_empty() && incoming.owner == resting.owner
+}
+
+/// Buy stops trigger once the market trades at or above the stop price,
+/// sell stops once it trades at or below it.
+fn stop_triggered(stop: &Order, last_trade_price: Option<Price>) -> bool {
+    match (last_trade_price, stop.action) {
+        (None, _) => false,
+        (Some(last), Action::Buy) => last >= stop.stop_price,
+        (Some(last), Action::Sell) => last <= stop.stop_price,
+    }
+}
+
+/// Whether a `action` order limited at `limit` can trade against a resting
+/// level at `price`. Market orders (no limit) cross every level.
+fn crosses(action: Action, price: Price, limit: Option<Price>) -> bool {
+    match (limit, action) {
+        (None, _) => true,
+        (Some(limit), Action::Buy) => price <= limit,
+        (Some(limit), Action::Sell) => price >= limit,
     }
 }
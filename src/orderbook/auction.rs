@@ -0,0 +1,200 @@
+use super::{
+    record, record_refills, touch, Action, BookDelta, ExecutionReport, OrderBook, OrderEvent, OrderEventKind,
+    StatusChange, Trade, TradingStatus,
+};
+use crate::price::Price;
+use serde::{Deserialize, Serialize};
+
+/// Price at which the auction would uncross if it ended now
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct IndicativeUncross {
+    pub price: Price,
+    /// Quantity that would trade at `price`
+    pub volume: i32,
+    /// Unmatched buy quantity at `price` minus unmatched sell quantity
+    pub imbalance: i32,
+}
+
+/// Result of `OrderBook::uncross`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct UncrossReport {
+    /// Clearing price, `None` when no orders crossed
+    pub price: Option<Price>,
+    pub volume: i32,
+    pub trades: Vec<Trade>,
+    pub status_change: Option<StatusChange>,
+    /// Stop orders triggered by the clearing price once trading reopened
+    pub triggered: Vec<ExecutionReport>,
+    /// Level changes made by the uncross and any triggered stops
+    pub delta: Option<BookDelta>,
+    /// Order events of the uncross and any triggered stops
+    pub order_events: Vec<OrderEvent>,
+}
+
+impl OrderBook {
+    /// Computes the equilibrium price of the current auction book.
+    ///
+    /// Every resting price is a candidate. The candidate with the highest
+    /// executable volume wins; ties go to the smallest absolute imbalance,
+    /// then to the price closest to the last trade price, then to the lowest
+    /// price. Returns `None` when no bid crosses any ask.
+    pub fn indicative_uncross(&self) -> Option<IndicativeUncross> {
+        // Buy volume at p counts bids priced >= p, sell volume counts asks <= p.
+        // Walking candidates upwards, bids drop out and asks join.
+        let mut bids: Vec<(Price, i32)> = self.bids.iter().map(|l| (l.price, l.executable_amount())).collect();
+        bids.reverse();
+        let asks: Vec<(Price, i32)> = self.asks.iter().map(|l| (l.price, l.executable_amount())).collect();
+        let mut candidates: Vec<Price> = bids.iter().chain(asks.iter()).map(|&(p, _)| p).collect();
+        candidates.sort();
+        candidates.dedup();
+
+        let mut buy_volume: i32 = bids.iter().map(|&(_, a)| a).sum();
+        let mut sell_volume = 0;
+        let (mut next_bid, mut next_ask) = (0, 0);
+        let mut best: Option<IndicativeUncross> = None;
+        for price in candidates {
+            while next_bid < bids.len() && bids[next_bid].0 < price {
+                buy_volume -= bids[next_bid].1;
+                next_bid += 1;
+            }
+            while next_ask < asks.len() && asks[next_ask].0 <= price {
+                sell_volume += asks[next_ask].1;
+                next_ask += 1;
+            }
+            let candidate = IndicativeUncross {
+                price,
+                volume: buy_volume.min(sell_volume),
+                imbalance: buy_volume - sell_volume,
+            };
+            if candidate.volume == 0 {
+                continue;
+            }
+            if best.is_none_or(|b| self.better_uncross(&candidate, &b)) {
+                best = Some(candidate);
+            }
+        }
+        best
+    }
+
+    fn better_uncross(&self, candidate: &IndicativeUncross, best: &IndicativeUncross) -> bool {
+        if candidate.volume != best.volume {
+            return candidate.volume > best.volume;
+        }
+        if candidate.imbalance.abs() != best.imbalance.abs() {
+            return candidate.imbalance.abs() < best.imbalance.abs();
+        }
+        if let Some(reference) = self.last_trade_price {
+            let candidate_distance = (candidate.price - reference).abs();
+            let best_distance = (best.price - reference).abs();
+            if candidate_distance != best_distance {
+                return candidate_distance < best_distance;
+            }
+        }
+        // Candidates are visited in ascending price order, so keep the first
+        false
+    }
+
+    /// Ends the auction: executes every crossing order at the single clearing
+    /// price from `indicative_uncross`, in price-time priority on both sides,
+    /// and returns the book to continuous trading. Does nothing unless the
+    /// book is in auction, so a halted or closed book stays that way.
+    pub fn uncross(&mut self, timestamp: u128) -> UncrossReport {
+        if self.trading_status != TradingStatus::Auction {
+            return UncrossReport::default();
+        }
+        let indicative = self.indicative_uncross();
+        let mut trades = Vec::new();
+
+        if let Some(IndicativeUncross { price, volume, .. }) = indicative {
+            let mut remaining = volume;
+            while remaining > 0 {
+                let (Some(mut bid_entry), Some(mut ask_entry)) =
+                    (self.bids.levels.first_entry(), self.asks.levels.first_entry())
+                else {
+                    break;
+                };
+                let (bid_level, ask_level) = (bid_entry.get_mut(), ask_entry.get_mut());
+                if bid_level.price < price || ask_level.price > price {
+                    break;
+                }
+                touch(&mut self.touched, Action::Buy, bid_level.price, Some(bid_level.total_amount));
+                touch(&mut self.touched, Action::Sell, ask_level.price, Some(ask_level.total_amount));
+                let (Some(bid), Some(ask)) =
+                    (bid_level.orders.front_mut(), ask_level.orders.front_mut())
+                else {
+                    break;
+                };
+
+                let fill = remaining.min(bid.amount).min(ask.amount);
+                // The later of the two orders is reported as the aggressor
+                let (aggressor, passive) = if bid.timestamp >= ask.timestamp {
+                    (&*bid, &*ask)
+                } else {
+                    (&*ask, &*bid)
+                };
+                trades.push(Trade {
+                    symbol: self.symbol.clone(),
+                    aggressor_id: aggressor.id,
+                    passive_id: passive.id,
+                    price,
+                    amount: fill,
+                    side: aggressor.action,
+                    timestamp,
+                });
+                bid.amount -= fill;
+                ask.amount -= fill;
+                bid_level.total_amount -= fill;
+                ask_level.total_amount -= fill;
+                remaining -= fill;
+                record(&mut self.events, &mut self.event_sequence, (OrderEventKind::Execute, fill, 0), bid);
+                record(&mut self.events, &mut self.event_sequence, (OrderEventKind::Execute, fill, 0), ask);
+
+                let (bid_done, ask_done) = (bid.amount == 0, ask.amount == 0);
+                if bid_done {
+                    let removed = bid_level.remove_exhausted(&[0]);
+                    let refilled = 1 - removed.len();
+                    for id in removed {
+                        self.index.remove(&id);
+                    }
+                    record_refills(&mut self.events, &mut self.event_sequence, bid_level, refilled);
+                }
+                if ask_done {
+                    let removed = ask_level.remove_exhausted(&[0]);
+                    let refilled = 1 - removed.len();
+                    for id in removed {
+                        self.index.remove(&id);
+                    }
+                    record_refills(&mut self.events, &mut self.event_sequence, ask_level, refilled);
+                }
+                if bid_level.orders.is_empty() {
+                    bid_entry.remove();
+                }
+                if ask_level.orders.is_empty() {
+                    ask_entry.remove();
+                }
+            }
+            self.last_trade_price = Some(price);
+            self.last_update = timestamp;
+            // The clearing price is the new reference for the volatility guard
+            self.recent_trades.clear();
+            if self.volatility_guard.is_some() {
+                self.recent_trades.push_back((timestamp, price));
+            }
+        }
+
+        let status_change = self.set_trading_status(TradingStatus::Open, timestamp);
+        let triggered = self.trigger_stops(timestamp);
+        let delta = self.finish_delta(timestamp);
+        let order_events = self.take_events(timestamp);
+        debug_assert!(!self.is_crossed(), "{} book crossed after uncross", self.symbol);
+        UncrossReport {
+            price: indicative.map(|i| i.price),
+            volume: indicative.map_or(0, |i| i.volume),
+            trades,
+            status_change,
+            triggered,
+            delta,
+            order_events,
+        }
+    }
+}
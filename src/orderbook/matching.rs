@@ -0,0 +1,118 @@
+use std::fmt;
+
+/// Decides how an incoming order's quantity is shared among the orders
+/// resting at a single price level. Each `OrderBook` has its own policy.
+pub trait MatchingPolicy: fmt::Debug + Send + Sync {
+    /// Allocates `quantity` among the resting orders whose displayed
+    /// quantities `resting` yields in queue order. Returns `(queue index, fill)`
+    /// pairs in queue order. No fill may exceed its order's displayed quantity
+    /// and the fills must add up to `quantity` or to the level total,
+    /// whichever is smaller.
+    fn allocate(&self, quantity: i32, resting: &mut dyn Iterator<Item = i32>) -> Vec<(usize, i32)>;
+}
+
+/// Price-time priority: the oldest order is filled first
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fifo;
+
+impl MatchingPolicy for Fifo {
+    fn allocate(&self, mut quantity: i32, resting: &mut dyn Iterator<Item = i32>) -> Vec<(usize, i32)> {
+        let mut fills = Vec::new();
+        for (idx, open) in resting.enumerate() {
+            if quantity == 0 {
+                break;
+            }
+            let fill = quantity.min(open);
+            fills.push((idx, fill));
+            quantity -= fill;
+        }
+        fills
+    }
+}
+
+/// Every order gets a share proportional to its displayed quantity, rounded
+/// down. Lots left over by rounding go one at a time to orders in queue order.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProRata;
+
+impl MatchingPolicy for ProRata {
+    fn allocate(&self, quantity: i32, resting: &mut dyn Iterator<Item = i32>) -> Vec<(usize, i32)> {
+        let sizes: Vec<i32> = resting.collect();
+        sparse(pro_rata(quantity, &sizes))
+    }
+}
+
+/// The order at the front of the queue is filled first, then whatever is
+/// left is shared pro-rata among the rest of the level
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProRataWithTop;
+
+impl MatchingPolicy for ProRataWithTop {
+    fn allocate(&self, quantity: i32, resting: &mut dyn Iterator<Item = i32>) -> Vec<(usize, i32)> {
+        let sizes: Vec<i32> = resting.collect();
+        let Some((&top, rest)) = sizes.split_first() else {
+            return Vec::new();
+        };
+        let top_fill = quantity.min(top);
+        let mut fills = vec![top_fill];
+        fills.extend(pro_rata(quantity - top_fill, rest));
+        sparse(fills)
+    }
+}
+
+fn pro_rata(quantity: i32, sizes: &[i32]) -> Vec<i32> {
+    let total: i64 = sizes.iter().map(|&s| s as i64).sum();
+    if total <= quantity as i64 {
+        return sizes.to_vec();
+    }
+    let mut fills: Vec<i32> = sizes
+        .iter()
+        .map(|&s| (quantity as i64 * s as i64 / total) as i32)
+        .collect();
+    // Fewer lots are left than orders that were rounded down, so one pass suffices
+    let mut leftover = quantity - fills.iter().sum::<i32>();
+    for (fill, &size) in fills.iter_mut().zip(sizes) {
+        if leftover == 0 {
+            break;
+        }
+        if *fill < size {
+            *fill += 1;
+            leftover -= 1;
+        }
+    }
+    fills
+}
+
+fn sparse(fills: Vec<i32>) -> Vec<(usize, i32)> {
+    fills.into_iter().enumerate().filter(|&(_, fill)| fill > 0).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allocate(policy: &dyn MatchingPolicy, quantity: i32, resting: &[i32]) -> Vec<(usize, i32)> {
+        policy.allocate(quantity, &mut resting.iter().copied())
+    }
+
+    #[test]
+    fn fifo_fills_in_queue_order() {
+        assert_eq!(allocate(&Fifo, 7, &[3, 3, 3]), vec![(0, 3), (1, 3), (2, 1)]);
+        assert_eq!(allocate(&Fifo, 20, &[3, 3]), vec![(0, 3), (1, 3)]);
+    }
+
+    #[test]
+    fn pro_rata_rounds_down_and_hands_out_leftovers_in_queue_order() {
+        assert_eq!(allocate(&ProRata, 4, &[3, 3, 3]), vec![(0, 2), (1, 1), (2, 1)]);
+        // 10 * 1/12 rounds to 0 but the first leftover lot still goes to the front
+        assert_eq!(allocate(&ProRata, 10, &[1, 5, 6]), vec![(0, 1), (1, 4), (2, 5)]);
+        assert_eq!(allocate(&ProRata, 1, &[5, 5]), vec![(0, 1)]);
+        assert_eq!(allocate(&ProRata, 20, &[3, 4]), vec![(0, 3), (1, 4)]);
+    }
+
+    #[test]
+    fn pro_rata_with_top_fills_the_front_first() {
+        assert_eq!(allocate(&ProRataWithTop, 6, &[2, 4, 4]), vec![(0, 2), (1, 2), (2, 2)]);
+        assert_eq!(allocate(&ProRataWithTop, 2, &[5, 4]), vec![(0, 2)]);
+    }
+}
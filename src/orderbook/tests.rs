@@ -0,0 +1,880 @@
+use super::*;
+
+pub(crate) fn price(value: f64) -> Price {
+    Price::from_f64(value).unwrap()
+}
+
+/// GTC order on TSLA stamped with its id
+pub(crate) fn order(id: u128, value: f64, amount: i32, action: Action, order_type: OrderType) -> Order {
+    Order {
+        id,
+        price: price(value),
+        amount,
+        action,
+        order_type,
+        timestamp: id,
+        instrument: "TSLA".to_string(),
+        time_in_force: TimeInForce::Gtc,
+        display_amount: 0,
+        hidden_amount: 0,
+        stop_price: Price::ZERO,
+        owner: String::new(),
+        post_only: false,
+    }
+}
+
+fn book() -> OrderBook {
+    OrderBook::new("TSLA".to_string())
+}
+
+fn owned(mut order: Order, owner: &str) -> Order {
+    order.owner = owner.to_string();
+    order
+}
+
+fn post_only(mut order: Order) -> Order {
+    order.post_only = true;
+    order
+}
+
+fn with_tif(mut order: Order, time_in_force: TimeInForce) -> Order {
+    order.time_in_force = time_in_force;
+    order
+}
+
+#[test]
+fn cancel_reduces_or_removes_the_resting_order() {
+    let mut book = book();
+    book.add_order(&order(1, 10.0, 5, Action::Buy, OrderType::Limit)).unwrap();
+    book.add_order(&order(2, 10.0, 7, Action::Buy, OrderType::Limit)).unwrap();
+
+    assert_eq!(book.cancel_order(1, 2), CancelResult::Reduced { id: 1, cancelled: 2, remaining: 3 });
+    assert_eq!(book.best_bid().unwrap().total_amount, 10);
+    assert_eq!(book.best_bid().unwrap().orders[0].id, 1);
+
+    // A Cancel message removes the rest of the order and never rests
+    let report = book.add_order(&order(1, 0.0, 0, Action::Buy, OrderType::Cancel)).unwrap();
+    assert_eq!((report.status, report.cancelled), (ExecStatus::Cancelled, 3));
+    assert_eq!(book.best_bid().unwrap().total_amount, 7);
+    assert_eq!(book.best_bid().unwrap().orders.len(), 1);
+
+    assert_eq!(book.cancel_order(2, 0), CancelResult::Cancelled { id: 2, cancelled: 7 });
+    assert!(book.bids.is_empty());
+    assert_eq!(book.cancel_order(2, 0), CancelResult::NotFound { id: 2 });
+    assert_eq!(
+        book.add_order(&order(2, 0.0, 0, Action::Buy, OrderType::Cancel)).unwrap_err(),
+        RejectReason::UnknownOrder
+    );
+    assert!(book.asks.is_empty());
+}
+
+#[test]
+fn market_orders_sweep_and_never_rest() {
+    let mut book = book();
+    book.add_order(&order(1, 10.0, 5, Action::Sell, OrderType::Limit)).unwrap();
+    book.add_order(&order(2, 11.0, 5, Action::Sell, OrderType::Limit)).unwrap();
+
+    // The price on a market order is ignored
+    let report = book.add_order(&order(3, 1.0, 7, Action::Buy, OrderType::Market)).unwrap();
+    assert_eq!((report.status, report.filled, report.resting), (ExecStatus::Filled, 7, 0));
+    let fills: Vec<_> = report.trades.iter().map(|t| (t.passive_id, t.price, t.amount)).collect();
+    assert_eq!(fills, vec![(1, price(10.0), 5), (2, price(11.0), 2)]);
+    assert_eq!(book.asks.len(), 1);
+    assert_eq!(book.best_ask().unwrap().total_amount, 3);
+
+    // Under Reject nothing trades unless the whole order can be filled
+    book.market_remainder_policy = MarketRemainderPolicy::Reject;
+    assert_eq!(
+        book.add_order(&order(4, 0.0, 4, Action::Buy, OrderType::Market)).unwrap_err(),
+        RejectReason::InsufficientLiquidity
+    );
+    assert_eq!(book.best_ask().unwrap().total_amount, 3);
+
+    // Under Cancel the remainder is dropped
+    book.market_remainder_policy = MarketRemainderPolicy::Cancel;
+    let report = book.add_order(&order(5, 0.0, 4, Action::Buy, OrderType::Market)).unwrap();
+    assert_eq!((report.status, report.filled, report.cancelled), (ExecStatus::Cancelled, 3, 1));
+    assert!(book.asks.is_empty());
+    assert!(book.bids.is_empty());
+}
+
+#[test]
+fn trades_describe_both_sides_of_each_fill() {
+    let mut book = book();
+    book.add_order(&order(1, 10.0, 2, Action::Buy, OrderType::Limit)).unwrap();
+    book.add_order(&order(2, 10.0, 3, Action::Buy, OrderType::Limit)).unwrap();
+
+    let report = book.add_order(&order(3, 0.0, 4, Action::Sell, OrderType::Market)).unwrap();
+    let expected = |passive_id, amount| Trade {
+        symbol: "TSLA".to_string(),
+        aggressor_id: 3,
+        passive_id,
+        price: price(10.0),
+        amount,
+        side: Action::Sell,
+        timestamp: 3,
+    };
+    assert_eq!(report.trades, vec![expected(1, 2), expected(2, 2)]);
+    assert_eq!(
+        report.trades[0].to_string(),
+        "TRADE: Sell 2 TSLA @ 10.00 matched with Buy order 1 for 2"
+    );
+}
+
+#[test]
+fn limit_orders_match_before_resting() {
+    let mut book = book();
+    book.add_order(&order(1, 10.0, 5, Action::Sell, OrderType::Limit)).unwrap();
+
+    let report = book.add_order(&order(2, 10.5, 8, Action::Buy, OrderType::Limit)).unwrap();
+    assert_eq!((report.status, report.filled, report.resting), (ExecStatus::PartiallyFilled, 5, 3));
+    assert_eq!(report.trades[0].price, price(10.0));
+    assert!(book.asks.is_empty());
+    let best = book.best_bid().unwrap();
+    assert_eq!((best.price, best.total_amount, best.orders[0].amount), (price(10.5), 3, 3));
+
+    let report = book.add_order(&order(3, 10.5, 3, Action::Sell, OrderType::Limit)).unwrap();
+    assert_eq!((report.status, report.resting), (ExecStatus::Filled, 0));
+    assert!(book.bids.is_empty() && book.asks.is_empty());
+
+    book.add_order(&order(4, 9.0, 1, Action::Buy, OrderType::Limit)).unwrap();
+    let report = book.add_order(&order(5, 9.5, 1, Action::Sell, OrderType::Limit)).unwrap();
+    assert_eq!((report.status, report.resting), (ExecStatus::New, 1));
+    assert!(!book.is_crossed());
+}
+
+#[test]
+fn levels_iterate_best_price_first() {
+    let mut book = book();
+    for (id, value) in [(1, 10.0), (2, 12.0), (3, 11.0), (4, 9.0)] {
+        book.add_order(&order(id, value, 1, Action::Buy, OrderType::Limit)).unwrap();
+        book.add_order(&order(id + 10, value + 5.0, 1, Action::Sell, OrderType::Limit)).unwrap();
+    }
+    let bids: Vec<_> = book.bids.iter().map(|level| level.price).collect();
+    assert_eq!(bids, vec![price(12.0), price(11.0), price(10.0), price(9.0)]);
+    let asks: Vec<_> = book.asks.iter().map(|level| level.price).collect();
+    assert_eq!(asks, vec![price(14.0), price(15.0), price(16.0), price(17.0)]);
+    assert_eq!(book.bids.get(price(11.0)).unwrap().total_amount, 1);
+
+    // Emptied levels are dropped from the map
+    book.add_order(&order(20, 11.0, 3, Action::Sell, OrderType::Limit)).unwrap();
+    assert_eq!(book.bids.len(), 2);
+    assert!(book.bids.get(price(12.0)).is_none());
+    assert_eq!(book.best_ask().unwrap().price, price(11.0));
+
+    let json = serde_json::to_string(&book).unwrap();
+    let restored: OrderBook = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.best_bid().unwrap().price, price(10.0));
+    assert_eq!(restored.asks.len(), 5);
+}
+
+#[test]
+fn index_tracks_resting_orders() {
+    let mut book = book();
+    book.add_order(&order(1, 10.0, 5, Action::Buy, OrderType::Limit)).unwrap();
+    book.add_order(&order(2, 10.0, 7, Action::Buy, OrderType::Limit)).unwrap();
+    book.add_order(&order(3, 10.0, 7, Action::Buy, OrderType::Limit)).unwrap();
+    assert_eq!(
+        book.get_order(3),
+        Some(OrderState { id: 3, side: Action::Buy, price: price(10.0), remaining: 7, displayed: 7, queue_position: 2 })
+    );
+
+    // Filled orders leave the index, partially filled ones keep their place
+    book.add_order(&order(4, 10.0, 6, Action::Sell, OrderType::Limit)).unwrap();
+    assert!(book.get_order(1).is_none());
+    let state = book.get_order(2).unwrap();
+    assert_eq!((state.remaining, state.queue_position), (6, 0));
+    assert_eq!(book.get_order(3).unwrap().queue_position, 1);
+
+    book.cancel_order(2, 0);
+    assert!(book.get_order(2).is_none());
+    assert_eq!(book.get_order(3).unwrap().queue_position, 0);
+    assert!(book.get_order(4).is_none());
+
+    let json = serde_json::to_string(&book).unwrap();
+    let mut restored: OrderBook = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.cancel_order(3, 0), CancelResult::Cancelled { id: 3, cancelled: 7 });
+}
+
+#[test]
+fn replace_keeps_priority_only_when_reducing() {
+    let mut book = book();
+    book.add_order(&order(1, 10.0, 5, Action::Buy, OrderType::Limit)).unwrap();
+    book.add_order(&order(2, 10.0, 5, Action::Buy, OrderType::Limit)).unwrap();
+
+    let report = book.add_order(&order(1, 10.0, 3, Action::Buy, OrderType::Replace)).unwrap();
+    assert_eq!((report.status, report.resting, report.cancelled), (ExecStatus::Replaced, 3, 2));
+    assert_eq!(book.get_order(1).unwrap().queue_position, 0);
+    assert_eq!(book.best_bid().unwrap().total_amount, 8);
+
+    // A quantity increase goes to the back of the queue
+    let report = book.add_order(&order(1, 10.0, 6, Action::Buy, OrderType::Replace)).unwrap();
+    assert_eq!((report.status, report.resting), (ExecStatus::Replaced, 6));
+    assert_eq!(book.get_order(1).unwrap().queue_position, 1);
+    assert_eq!(book.get_order(2).unwrap().queue_position, 0);
+}
+
+#[test]
+fn replace_reprices_on_the_original_side() {
+    let mut book = book();
+    book.add_order(&order(1, 10.0, 5, Action::Buy, OrderType::Limit)).unwrap();
+    book.add_order(&order(2, 11.0, 2, Action::Sell, OrderType::Limit)).unwrap();
+
+    // The side on the amend is ignored and a crossing price trades first
+    let report = book.add_order(&order(1, 11.0, 5, Action::Sell, OrderType::Replace)).unwrap();
+    assert_eq!((report.status, report.filled, report.resting), (ExecStatus::PartiallyFilled, 2, 3));
+    assert_eq!(report.trades[0].side, Action::Buy);
+    let state = book.get_order(1).unwrap();
+    assert_eq!((state.side, state.price, state.remaining), (Action::Buy, price(11.0), 3));
+    assert!(book.asks.is_empty());
+    assert!(book.bids.get(price(10.0)).is_none());
+}
+
+#[test]
+fn replace_to_zero_cancels_and_unknown_ids_are_rejected() {
+    let mut book = book();
+    book.add_order(&order(1, 10.0, 5, Action::Buy, OrderType::Limit)).unwrap();
+
+    let report = book.add_order(&order(1, 0.0, 0, Action::Buy, OrderType::Replace)).unwrap();
+    assert_eq!((report.status, report.filled, report.cancelled), (ExecStatus::Cancelled, 0, 5));
+    assert!(book.get_order(1).is_none());
+    assert!(book.bids.is_empty());
+
+    assert_eq!(
+        book.add_order(&order(1, 10.0, 1, Action::Buy, OrderType::Replace)).unwrap_err(),
+        RejectReason::UnknownOrder
+    );
+    assert!(book.bids.is_empty());
+}
+
+#[test]
+fn ioc_cancels_and_fok_refuses_what_does_not_fill() {
+    let mut book = book();
+    book.add_order(&order(1, 10.0, 5, Action::Sell, OrderType::Limit)).unwrap();
+    book.add_order(&order(2, 11.0, 5, Action::Sell, OrderType::Limit)).unwrap();
+
+    let fok = with_tif(order(3, 10.0, 6, Action::Buy, OrderType::Limit), TimeInForce::Fok);
+    assert_eq!(book.add_order(&fok).unwrap_err(), RejectReason::InsufficientLiquidity);
+    assert_eq!(book.best_ask().unwrap().total_amount, 5);
+
+    let fok = with_tif(order(4, 11.0, 6, Action::Buy, OrderType::Limit), TimeInForce::Fok);
+    let report = book.add_order(&fok).unwrap();
+    assert_eq!((report.status, report.filled), (ExecStatus::Filled, 6));
+
+    let ioc = with_tif(order(5, 11.0, 10, Action::Buy, OrderType::Limit), TimeInForce::Ioc);
+    let report = book.add_order(&ioc).unwrap();
+    assert_eq!((report.status, report.filled, report.cancelled, report.resting), (ExecStatus::Cancelled, 4, 6, 0));
+    assert!(book.asks.is_empty());
+    assert!(book.bids.is_empty());
+
+    // A FOK market order is all-or-none whatever the remainder policy
+    book.add_order(&order(6, 12.0, 2, Action::Sell, OrderType::Limit)).unwrap();
+    let fok = with_tif(order(7, 0.0, 3, Action::Buy, OrderType::Market), TimeInForce::Fok);
+    assert_eq!(book.add_order(&fok).unwrap_err(), RejectReason::InsufficientLiquidity);
+    assert_eq!(book.best_ask().unwrap().total_amount, 2);
+}
+
+#[test]
+fn day_orders_expire_at_the_end_of_the_session() {
+    let mut book = book();
+    book.add_order(&with_tif(order(1, 10.0, 5, Action::Buy, OrderType::Limit), TimeInForce::Day)).unwrap();
+    book.add_order(&order(2, 10.0, 5, Action::Buy, OrderType::Limit)).unwrap();
+    book.add_order(&with_tif(order(3, 12.0, 4, Action::Sell, OrderType::Limit), TimeInForce::Day)).unwrap();
+
+    // A replace keeps the time in force of the resting order
+    book.add_order(&order(1, 9.0, 5, Action::Buy, OrderType::Replace)).unwrap();
+
+    let mut expired: Vec<_> = book.end_session().iter().map(|r| (r.order_id, r.status, r.cancelled)).collect();
+    expired.sort_by_key(|&(id, _, _)| id);
+    assert_eq!(expired, vec![(1, ExecStatus::Expired, 5), (3, ExecStatus::Expired, 4)]);
+    assert!(book.asks.is_empty());
+    assert_eq!(book.get_order(2).unwrap().remaining, 5);
+    assert!(book.end_session().is_empty());
+}
+
+#[test]
+fn only_cancels_are_accepted_unless_open() {
+    let mut book = book();
+    book.add_order(&order(1, 10.0, 5, Action::Buy, OrderType::Limit)).unwrap();
+
+    let change = book.set_trading_status(TradingStatus::Halted, 7).unwrap();
+    assert_eq!((change.from, change.to, change.timestamp), (TradingStatus::Open, TradingStatus::Halted, 7));
+    assert!(book.set_trading_status(TradingStatus::Halted, 8).is_none());
+
+    let halted = RejectReason::BookNotOpen(TradingStatus::Halted);
+    assert_eq!(book.add_order(&order(2, 10.0, 5, Action::Sell, OrderType::Limit)).unwrap_err(), halted);
+    assert_eq!(book.add_order(&order(1, 9.0, 5, Action::Buy, OrderType::Replace)).unwrap_err(), halted);
+    assert_eq!(book.get_order(1).unwrap().price, price(10.0));
+
+    let report = book.add_order(&order(1, 0.0, 0, Action::Buy, OrderType::Cancel)).unwrap();
+    assert_eq!(report.status, ExecStatus::Cancelled);
+    assert!(book.bids.is_empty());
+
+    // Each book has its own status
+    let mut other = OrderBook::new("AAPL".to_string());
+    assert_eq!(other.trading_status(), TradingStatus::Open);
+    let aapl = Order {
+        instrument: "AAPL".to_string(),
+        ..order(3, 10.0, 5, Action::Buy, OrderType::Limit)
+    };
+    assert_eq!(other.add_order(&aapl).unwrap().status, ExecStatus::New);
+
+    book.set_trading_status(TradingStatus::Open, 9);
+    assert_eq!(book.add_order(&order(2, 10.0, 5, Action::Sell, OrderType::Limit)).unwrap().status, ExecStatus::New);
+}
+
+fn auction_book() -> OrderBook {
+    let mut book = book();
+    book.set_trading_status(TradingStatus::Auction, 0);
+    book
+}
+
+#[test]
+fn auction_orders_rest_without_matching() {
+    let mut book = auction_book();
+    let report = book.add_order(&order(1, 10.0, 5, Action::Sell, OrderType::Limit)).unwrap();
+    assert_eq!(report.status, ExecStatus::New);
+    let report = book.add_order(&order(2, 11.0, 3, Action::Buy, OrderType::Limit)).unwrap();
+    assert_eq!((report.status, report.resting), (ExecStatus::New, 3));
+    assert!(report.trades.is_empty());
+    assert!(book.is_crossed());
+
+    let not_open = RejectReason::BookNotOpen(TradingStatus::Auction);
+    assert_eq!(book.add_order(&order(3, 0.0, 1, Action::Buy, OrderType::Market)).unwrap_err(), not_open);
+    let ioc = with_tif(order(4, 11.0, 1, Action::Buy, OrderType::Limit), TimeInForce::Ioc);
+    assert_eq!(book.add_order(&ioc).unwrap_err(), not_open);
+}
+
+#[test]
+fn most_volume_wins() {
+    let mut book = auction_book();
+    book.add_order(&order(1, 10.0, 10, Action::Buy, OrderType::Limit)).unwrap();
+    book.add_order(&order(2, 9.0, 5, Action::Buy, OrderType::Limit)).unwrap();
+    book.add_order(&order(3, 8.0, 8, Action::Sell, OrderType::Limit)).unwrap();
+    book.add_order(&order(4, 9.0, 6, Action::Sell, OrderType::Limit)).unwrap();
+
+    let indicative = book.indicative_uncross().unwrap();
+    assert_eq!(indicative, IndicativeUncross { price: price(9.0), volume: 14, imbalance: 1 });
+}
+
+#[test]
+fn smallest_imbalance_breaks_volume_ties() {
+    let mut book = auction_book();
+    book.add_order(&order(1, 10.0, 5, Action::Buy, OrderType::Limit)).unwrap();
+    book.add_order(&order(2, 9.0, 2, Action::Buy, OrderType::Limit)).unwrap();
+    book.add_order(&order(3, 9.0, 5, Action::Sell, OrderType::Limit)).unwrap();
+
+    // Both 9.00 and 10.00 trade 5; at 9.00 two lots of buying are left over
+    let indicative = book.indicative_uncross().unwrap();
+    assert_eq!(indicative, IndicativeUncross { price: price(10.0), volume: 5, imbalance: 0 });
+}
+
+#[test]
+fn reference_price_then_lowest_price_break_remaining_ties() {
+    let crossed = |book: &mut OrderBook| {
+        book.set_trading_status(TradingStatus::Auction, 0);
+        book.add_order(&order(11, 12.0, 5, Action::Buy, OrderType::Limit)).unwrap();
+        book.add_order(&order(12, 10.0, 5, Action::Sell, OrderType::Limit)).unwrap();
+    };
+
+    let mut without_reference = book();
+    crossed(&mut without_reference);
+    assert_eq!(without_reference.indicative_uncross().unwrap().price, price(10.0));
+
+    let mut with_reference = book();
+    with_reference.add_order(&order(1, 11.9, 1, Action::Sell, OrderType::Limit)).unwrap();
+    with_reference.add_order(&order(2, 11.9, 1, Action::Buy, OrderType::Limit)).unwrap();
+    assert_eq!(with_reference.last_trade_price(), Some(price(11.9)));
+    crossed(&mut with_reference);
+    assert_eq!(with_reference.indicative_uncross().unwrap().price, price(12.0));
+}
+
+#[test]
+fn uncross_trades_at_one_price_and_reopens() {
+    let mut book = auction_book();
+    book.add_order(&order(1, 10.0, 4, Action::Buy, OrderType::Limit)).unwrap();
+    book.add_order(&order(2, 9.5, 4, Action::Buy, OrderType::Limit)).unwrap();
+    book.add_order(&order(3, 9.0, 3, Action::Sell, OrderType::Limit)).unwrap();
+    book.add_order(&order(4, 9.5, 3, Action::Sell, OrderType::Limit)).unwrap();
+
+    let report = book.uncross(100);
+    assert_eq!((report.price, report.volume), (Some(price(9.5)), 6));
+    assert!(report.trades.iter().all(|t| t.price == price(9.5) && t.timestamp == 100));
+    let fills: Vec<_> = report.trades.iter().map(|t| (t.aggressor_id, t.passive_id, t.amount)).collect();
+    assert_eq!(fills, vec![(3, 1, 3), (4, 1, 1), (4, 2, 2)]);
+    assert_eq!(report.status_change.unwrap().to, TradingStatus::Open);
+    assert_eq!(book.trading_status(), TradingStatus::Open);
+    assert!(!book.is_crossed());
+    assert_eq!(book.get_order(2).unwrap().remaining, 2);
+    assert_eq!(book.last_trade_price(), Some(price(9.5)));
+}
+
+#[test]
+fn no_cross_no_uncross() {
+    let mut book = auction_book();
+    book.add_order(&order(1, 9.0, 4, Action::Buy, OrderType::Limit)).unwrap();
+    book.add_order(&order(2, 10.0, 4, Action::Sell, OrderType::Limit)).unwrap();
+    assert!(book.indicative_uncross().is_none());
+
+    let report = book.uncross(5);
+    assert_eq!((report.price, report.volume), (None, 0));
+    assert!(report.trades.is_empty());
+    assert_eq!(book.trading_status(), TradingStatus::Open);
+}
+
+#[test]
+fn a_crossed_book_leaves_auction_only_through_uncross() {
+    let mut book = auction_book();
+    book.add_order(&order(1, 10.0, 4, Action::Buy, OrderType::Limit)).unwrap();
+    book.add_order(&order(2, 9.0, 4, Action::Sell, OrderType::Limit)).unwrap();
+
+    assert!(book.set_trading_status(TradingStatus::Open, 1).is_none());
+    assert_eq!(book.trading_status(), TradingStatus::Auction);
+
+    // A halt is allowed, and orders can still be pulled while halted
+    assert!(book.set_trading_status(TradingStatus::Halted, 2).is_some());
+    assert_eq!(
+        book.add_order(&order(3, 0.0, 0, Action::Buy, OrderType::Cancel)).unwrap_err(),
+        RejectReason::UnknownOrder
+    );
+
+    // Uncross does not lift the halt
+    assert_eq!(book.uncross(3), UncrossReport::default());
+    assert_eq!(book.trading_status(), TradingStatus::Halted);
+    assert!(book.set_trading_status(TradingStatus::Open, 4).is_none());
+
+    book.set_trading_status(TradingStatus::Auction, 5);
+    let report = book.uncross(6);
+    assert_eq!(report.volume, 4);
+    assert_eq!(book.trading_status(), TradingStatus::Open);
+}
+
+fn iceberg(id: u128, value: f64, amount: i32, display_amount: i32, action: Action) -> Order {
+    let mut order = order(id, value, amount, action, OrderType::Limit);
+    order.display_amount = display_amount;
+    order
+}
+
+#[test]
+fn iceberg_refills_to_back() {
+    let mut book = book();
+    book.add_order(&iceberg(1, 10.0, 5, 2, Action::Sell)).unwrap();
+    book.add_order(&order(2, 10.0, 2, Action::Sell, OrderType::Limit)).unwrap();
+    assert_eq!(book.best_ask().unwrap().total_amount, 4);
+    assert_eq!(book.best_ask().unwrap().executable_amount(), 7);
+
+    let report = book.add_order(&order(3, 10.0, 3, Action::Buy, OrderType::Limit)).unwrap();
+    let fills: Vec<_> = report.trades.iter().map(|t| (t.passive_id, t.amount)).collect();
+    assert_eq!(fills, vec![(1, 2), (2, 1)]);
+    let state = book.get_order(1).unwrap();
+    assert_eq!((state.queue_position, state.displayed, state.remaining), (1, 2, 3));
+    assert_eq!(book.best_ask().unwrap().total_amount, 3);
+
+    // The hidden reserve trades once the displayed peaks ahead of it are gone
+    let report = book.add_order(&order(4, 10.0, 6, Action::Buy, OrderType::Limit)).unwrap();
+    let fills: Vec<_> = report.trades.iter().map(|t| (t.passive_id, t.amount)).collect();
+    assert_eq!(fills, vec![(2, 1), (1, 2), (1, 1)]);
+    assert_eq!((report.filled, report.resting), (4, 2));
+    assert!(book.asks.is_empty());
+}
+
+#[test]
+fn iceberg_cancels_and_replaces() {
+    let mut book = book();
+    book.add_order(&iceberg(1, 10.0, 10, 3, Action::Buy)).unwrap();
+
+    // Partial cancels come out of the reserve first
+    assert_eq!(book.cancel_order(1, 5), CancelResult::Reduced { id: 1, cancelled: 5, remaining: 5 });
+    let state = book.get_order(1).unwrap();
+    assert_eq!((state.displayed, state.remaining), (3, 5));
+    assert_eq!(book.best_bid().unwrap().total_amount, 3);
+
+    // A replace keeps the peak size and splits the new quantity again
+    book.add_order(&order(1, 11.0, 8, Action::Buy, OrderType::Replace)).unwrap();
+    let state = book.get_order(1).unwrap();
+    assert_eq!((state.price, state.displayed, state.remaining), (price(11.0), 3, 8));
+
+    let report = book.add_order(&order(1, 0.0, 0, Action::Buy, OrderType::Cancel)).unwrap();
+    assert_eq!(report.cancelled, 8);
+    assert!(book.bids.is_empty());
+}
+
+#[test]
+fn fok_counts_hidden_reserve() {
+    let mut book = book();
+    book.add_order(&iceberg(1, 10.0, 10, 2, Action::Sell)).unwrap();
+    let fok = with_tif(order(2, 10.0, 9, Action::Buy, OrderType::Limit), TimeInForce::Fok);
+    assert_eq!(book.add_order(&fok).unwrap().status, ExecStatus::Filled);
+    assert_eq!(book.get_order(1).unwrap().remaining, 1);
+}
+
+fn stop(id: u128, value: f64, stop_price: f64, amount: i32, action: Action, order_type: OrderType) -> Order {
+    let mut order = order(id, value, amount, action, order_type);
+    order.stop_price = price(stop_price);
+    order
+}
+
+#[test]
+fn stops_wait_for_the_last_trade_price() {
+    let mut book = book();
+    book.add_order(&order(1, 10.0, 5, Action::Sell, OrderType::Limit)).unwrap();
+    book.add_order(&order(2, 11.0, 5, Action::Sell, OrderType::Limit)).unwrap();
+
+    let report = book.add_order(&stop(3, 0.0, 10.0, 3, Action::Buy, OrderType::Stop)).unwrap();
+    assert_eq!(report.status, ExecStatus::Pending);
+    assert!(report.triggered.is_empty());
+    assert!(book.get_order(3).is_none());
+
+    // A trade at 10.00 releases the stop as a market order, stamped with the
+    // time of the trade that triggered it
+    let report = book.add_order(&order(4, 10.0, 1, Action::Buy, OrderType::Limit)).unwrap();
+    assert_eq!(report.triggered.len(), 1);
+    let released = &report.triggered[0];
+    assert_eq!((released.order_id, released.status, released.filled), (3, ExecStatus::Filled, 3));
+    assert!(released.trades.iter().all(|t| t.timestamp == 4));
+    let fills: Vec<_> = report.all_trades().map(|t| (t.aggressor_id, t.price, t.amount)).collect();
+    assert_eq!(fills, vec![(4, price(10.0), 1), (3, price(10.0), 3)]);
+}
+
+#[test]
+fn stops_cascade_in_arrival_order() {
+    let mut book = book();
+    book.add_order(&order(1, 10.0, 2, Action::Buy, OrderType::Limit)).unwrap();
+    book.add_order(&order(2, 9.0, 2, Action::Buy, OrderType::Limit)).unwrap();
+    book.add_order(&order(3, 8.0, 2, Action::Buy, OrderType::Limit)).unwrap();
+    book.add_order(&stop(4, 0.0, 9.0, 2, Action::Sell, OrderType::Stop)).unwrap();
+    book.add_order(&stop(5, 7.5, 10.0, 2, Action::Sell, OrderType::StopLimit)).unwrap();
+
+    // Selling into 10.00 releases stop 5, which trades at 9.00 and releases stop 4
+    let report = book.add_order(&order(6, 10.0, 2, Action::Sell, OrderType::Limit)).unwrap();
+    let released: Vec<_> = report.triggered.iter().map(|r| (r.order_id, r.filled)).collect();
+    assert_eq!(released, vec![(5, 2), (4, 2)]);
+    assert!(book.bids.is_empty());
+    assert_eq!(book.last_trade_price(), Some(price(8.0)));
+}
+
+#[test]
+fn stop_limits_rest_at_their_limit_once_triggered() {
+    let mut book = book();
+    book.add_order(&stop(1, 10.5, 10.0, 4, Action::Buy, OrderType::StopLimit)).unwrap();
+    book.add_order(&order(2, 10.0, 1, Action::Sell, OrderType::Limit)).unwrap();
+    let report = book.add_order(&order(3, 10.0, 1, Action::Buy, OrderType::Limit)).unwrap();
+    assert_eq!(report.triggered[0].status, ExecStatus::New);
+    let state = book.get_order(1).unwrap();
+    assert_eq!((state.side, state.price, state.remaining), (Action::Buy, price(10.5), 4));
+}
+
+#[test]
+fn pending_stops_can_be_cancelled_and_expired_but_not_amended() {
+    let mut book = book();
+    book.add_order(&stop(1, 0.0, 12.0, 5, Action::Buy, OrderType::Stop)).unwrap();
+    book.add_order(&with_tif(stop(2, 0.0, 8.0, 5, Action::Sell, OrderType::Stop), TimeInForce::Day)).unwrap();
+
+    assert_eq!(book.cancel_order(1, 2), CancelResult::Reduced { id: 1, cancelled: 2, remaining: 3 });
+    let report = book.add_order(&order(1, 0.0, 0, Action::Buy, OrderType::Cancel)).unwrap();
+    assert_eq!((report.status, report.cancelled), (ExecStatus::Cancelled, 3));
+
+    assert_eq!(
+        book.add_order(&order(2, 9.0, 4, Action::Sell, OrderType::Replace)).unwrap_err(),
+        RejectReason::UnknownOrder
+    );
+
+    let expired = book.end_session();
+    assert_eq!((expired[0].order_id, expired[0].status, expired[0].cancelled), (2, ExecStatus::Expired, 5));
+}
+
+#[test]
+fn uncross_releases_stops_at_the_clearing_price() {
+    let mut book = auction_book();
+    book.add_order(&stop(1, 0.0, 10.0, 2, Action::Sell, OrderType::Stop)).unwrap();
+    book.add_order(&order(2, 10.0, 5, Action::Buy, OrderType::Limit)).unwrap();
+    book.add_order(&order(3, 10.0, 2, Action::Sell, OrderType::Limit)).unwrap();
+
+    let report = book.uncross(50);
+    assert_eq!(report.price, Some(price(10.0)));
+    assert_eq!(report.triggered.len(), 1);
+    let released = &report.triggered[0];
+    assert_eq!((released.order_id, released.filled), (1, 2));
+    assert_eq!(released.trades[0].timestamp, 50);
+    assert_eq!(book.get_order(2).unwrap().remaining, 1);
+}
+
+#[test]
+fn post_only_rejects_or_reprices_behind_the_touch() {
+    let mut book = book();
+    book.add_order(&order(1, 10.0, 5, Action::Sell, OrderType::Limit)).unwrap();
+
+    assert_eq!(
+        book.add_order(&post_only(order(2, 10.0, 5, Action::Buy, OrderType::Limit))).unwrap_err(),
+        RejectReason::PostOnlyWouldCross
+    );
+    assert!(book.bids.is_empty());
+
+    book.post_only_policy = PostOnlyPolicy::Reprice;
+    let report = book.add_order(&post_only(order(3, 10.5, 5, Action::Buy, OrderType::Limit))).unwrap();
+    assert_eq!((report.status, report.resting), (ExecStatus::New, 5));
+    assert_eq!(book.get_order(3).unwrap().price, price(9.99));
+
+    // Post-only orders that do not cross rest as they are
+    book.add_order(&post_only(order(4, 9.0, 5, Action::Buy, OrderType::Limit))).unwrap();
+    assert_eq!(book.get_order(4).unwrap().price, price(9.0));
+}
+
+#[test]
+fn self_trade_prevention_modes() {
+    let run = |stp| {
+        let mut book = book();
+        book.self_trade_prevention = stp;
+        book.add_order(&owned(order(1, 10.0, 5, Action::Sell, OrderType::Limit), "A")).unwrap();
+        let report = book.add_order(&owned(order(2, 10.0, 3, Action::Buy, OrderType::Limit), "A")).unwrap();
+        (book, report)
+    };
+
+    let (book, report) = run(SelfTradePrevention::Allow);
+    assert_eq!(report.filled, 3);
+    assert_eq!(book.get_order(1).unwrap().remaining, 2);
+
+    let (book, report) = run(SelfTradePrevention::CancelNewest);
+    assert_eq!((report.status, report.filled, report.cancelled), (ExecStatus::Cancelled, 0, 3));
+    assert!(report.self_trade_cancels.is_empty());
+    assert_eq!(book.get_order(1).unwrap().remaining, 5);
+
+    let (book, report) = run(SelfTradePrevention::CancelOldest);
+    assert_eq!(report.self_trade_cancels, vec![CancelResult::Cancelled { id: 1, cancelled: 5 }]);
+    assert_eq!((report.status, report.resting), (ExecStatus::New, 3));
+    assert_eq!(book.get_order(2).unwrap().remaining, 3);
+
+    let (book, report) = run(SelfTradePrevention::CancelBoth);
+    assert_eq!(report.self_trade_cancels, vec![CancelResult::Cancelled { id: 1, cancelled: 5 }]);
+    assert_eq!((report.status, report.cancelled), (ExecStatus::Cancelled, 3));
+    assert!(book.asks.is_empty() && book.bids.is_empty());
+
+    let (book, report) = run(SelfTradePrevention::Decrement);
+    assert_eq!(report.self_trade_cancels, vec![CancelResult::Reduced { id: 1, cancelled: 3, remaining: 2 }]);
+    assert_eq!((report.status, report.filled, report.cancelled), (ExecStatus::Cancelled, 0, 3));
+    assert_eq!(book.get_order(1).unwrap().remaining, 2);
+}
+
+#[test]
+fn self_trade_prevention_trades_ahead_of_own_order() {
+    let mut book = book();
+    book.self_trade_prevention = SelfTradePrevention::CancelNewest;
+    book.add_order(&owned(order(1, 10.0, 2, Action::Sell, OrderType::Limit), "B")).unwrap();
+    book.add_order(&owned(order(2, 10.0, 5, Action::Sell, OrderType::Limit), "A")).unwrap();
+
+    let report = book.add_order(&owned(order(3, 10.0, 4, Action::Buy, OrderType::Limit), "A")).unwrap();
+    assert_eq!((report.filled, report.cancelled), (2, 2));
+    assert_eq!(report.trades[0].passive_id, 1);
+    assert_eq!(book.get_order(2).unwrap().remaining, 5);
+
+    // An all-or-none order cannot count liquidity behind its own order
+    let fok = with_tif(owned(order(4, 10.0, 1, Action::Buy, OrderType::Limit), "A"), TimeInForce::Fok);
+    assert_eq!(book.add_order(&fok).unwrap_err(), RejectReason::InsufficientLiquidity);
+}
+
+#[test]
+fn replace_keeps_owner_and_post_only() {
+    let mut book = book();
+    book.self_trade_prevention = SelfTradePrevention::CancelNewest;
+    book.add_order(&post_only(owned(order(1, 10.0, 5, Action::Buy, OrderType::Limit), "A"))).unwrap();
+    book.add_order(&order(1, 11.0, 5, Action::Buy, OrderType::Replace)).unwrap();
+
+    let report = book.add_order(&owned(order(2, 11.0, 5, Action::Sell, OrderType::Limit), "A")).unwrap();
+    assert!(report.trades.is_empty());
+    assert_eq!(report.cancelled, 5);
+
+    // A replacement that would cross is refused and the original keeps resting
+    book.add_order(&order(3, 12.0, 5, Action::Sell, OrderType::Limit)).unwrap();
+    assert_eq!(
+        book.add_order(&order(1, 12.0, 5, Action::Buy, OrderType::Replace)).unwrap_err(),
+        RejectReason::PostOnlyWouldCross
+    );
+    let state = book.get_order(1).unwrap();
+    assert_eq!((state.price, state.remaining, state.queue_position), (price(11.0), 5, 0));
+}
+
+#[test]
+fn pro_rata_level_allocation() {
+    let mut book = book();
+    book.matching_policy = Arc::new(ProRata);
+    book.add_order(&order(1, 10.0, 3, Action::Sell, OrderType::Limit)).unwrap();
+    book.add_order(&order(2, 10.0, 3, Action::Sell, OrderType::Limit)).unwrap();
+    book.add_order(&order(3, 10.0, 3, Action::Sell, OrderType::Limit)).unwrap();
+
+    let report = book.add_order(&order(4, 10.0, 4, Action::Buy, OrderType::Limit)).unwrap();
+    let fills: Vec<_> = report.trades.iter().map(|t| (t.passive_id, t.amount)).collect();
+    assert_eq!(fills, vec![(1, 2), (2, 1), (3, 1)]);
+    assert_eq!(book.best_ask().unwrap().total_amount, 5);
+    assert_eq!(book.get_order(1).unwrap().remaining, 1);
+}
+
+#[test]
+fn static_and_dynamic_collars_refuse_far_prices() {
+    let mut book = book();
+    book.price_collar = Some(PriceCollar { min: price(5.0), max: price(20.0) });
+    assert_eq!(
+        book.add_order(&order(1, 25.0, 5, Action::Sell, OrderType::Limit)).unwrap_err(),
+        RejectReason::PriceOutsideCollar
+    );
+
+    book.dynamic_collar_bps = Some(1000);
+    book.add_order(&order(2, 10.0, 5, Action::Sell, OrderType::Limit)).unwrap();
+    book.add_order(&order(3, 10.0, 1, Action::Buy, OrderType::Limit)).unwrap();
+    assert_eq!(book.dynamic_collar(), Some(PriceCollar { min: price(9.0), max: price(11.0) }));
+    assert_eq!(
+        book.add_order(&order(4, 11.5, 1, Action::Sell, OrderType::Limit)).unwrap_err(),
+        RejectReason::PriceOutsideCollar
+    );
+
+    // A market sweep stops at the edge of the dynamic collar
+    book.add_order(&order(5, 11.0, 5, Action::Sell, OrderType::Limit)).unwrap();
+    book.dynamic_collar_bps = None;
+    book.add_order(&order(6, 12.0, 5, Action::Sell, OrderType::Limit)).unwrap();
+    book.dynamic_collar_bps = Some(1000);
+    let report = book.add_order(&order(7, 0.0, 20, Action::Buy, OrderType::Market)).unwrap();
+    assert_eq!((report.filled, report.cancelled), (9, 11));
+    assert_eq!(book.best_ask().unwrap().price, price(12.0));
+}
+
+#[test]
+fn volatility_guard_interrupts_trading_until_the_auction_ends() {
+    let mut book = book();
+    book.volatility_guard = Some(VolatilityGuard { max_move_bps: 500, window: 100, auction_duration: 50 });
+    book.add_order(&order(1, 10.0, 5, Action::Sell, OrderType::Limit)).unwrap();
+    book.add_order(&order(2, 10.4, 5, Action::Sell, OrderType::Limit)).unwrap();
+    book.add_order(&order(3, 11.0, 5, Action::Sell, OrderType::Limit)).unwrap();
+    assert_eq!(book.auction_end(), None);
+
+    // 11.0 is more than 5% away from the first trade at 10.0
+    let report = book.add_order(&order(4, 11.0, 15, Action::Buy, OrderType::Limit)).unwrap();
+    assert_eq!((report.filled, report.resting), (10, 5));
+    assert_eq!(report.status_change.unwrap().to, TradingStatus::Auction);
+    assert_eq!(book.auction_end(), Some(54));
+
+    let report = book.uncross(54);
+    assert_eq!((report.price, report.volume), (Some(price(11.0)), 5));
+    assert_eq!(book.trading_status(), TradingStatus::Open);
+    assert_eq!(book.auction_end(), None);
+}
+
+#[test]
+fn all_or_none_respects_volatility_guard() {
+    let guarded = || {
+        let mut book = book();
+        book.volatility_guard = Some(VolatilityGuard { max_move_bps: 1000, window: 1_000_000, auction_duration: 0 });
+        book.add_order(&order(1, 10.0, 6, Action::Sell, OrderType::Limit)).unwrap();
+        book.add_order(&order(2, 10.0, 1, Action::Buy, OrderType::Limit)).unwrap();
+        book.add_order(&order(3, 12.0, 5, Action::Sell, OrderType::Limit)).unwrap();
+        book
+    };
+
+    let mut book = guarded();
+    let fok = with_tif(order(4, 12.0, 9, Action::Buy, OrderType::Limit), TimeInForce::Fok);
+    assert_eq!(book.add_order(&fok).unwrap_err(), RejectReason::InsufficientLiquidity);
+    assert_eq!(book.trading_status(), TradingStatus::Open);
+    assert!(book.get_order(4).is_none());
+
+    let mut book = guarded();
+    book.market_remainder_policy = MarketRemainderPolicy::Reject;
+    assert_eq!(
+        book.add_order(&order(4, 0.0, 9, Action::Buy, OrderType::Market)).unwrap_err(),
+        RejectReason::InsufficientLiquidity
+    );
+
+    // Without all-or-none the sweep stops at the guard and the book goes to auction
+    let mut book = guarded();
+    let report = book.add_order(&order(4, 12.0, 9, Action::Buy, OrderType::Limit)).unwrap();
+    assert_eq!((report.filled, report.resting), (5, 4));
+    assert_eq!(book.trading_status(), TradingStatus::Auction);
+}
+
+#[test]
+fn snapshot_and_level_deltas() {
+    let mut book = book();
+    let report = book.add_order(&order(1, 10.0, 5, Action::Buy, OrderType::Limit)).unwrap();
+    let delta = report.delta.unwrap();
+    assert_eq!(delta.sequence, 1);
+    assert_eq!(
+        delta.levels,
+        vec![LevelDelta { side: Action::Buy, price: price(10.0), amount: 5, change: LevelChange::New }]
+    );
+    book.add_order(&order(2, 9.0, 3, Action::Buy, OrderType::Limit)).unwrap();
+    book.add_order(&order(3, 11.0, 4, Action::Sell, OrderType::Limit)).unwrap();
+
+    // Sweeps 10.00 and part of 9.00
+    let delta = book.add_order(&order(4, 9.0, 7, Action::Sell, OrderType::Limit)).unwrap().delta.unwrap();
+    assert_eq!(delta.sequence, 4);
+    assert_eq!(
+        delta.levels,
+        vec![
+            LevelDelta { side: Action::Buy, price: price(10.0), amount: 0, change: LevelChange::Delete },
+            LevelDelta { side: Action::Buy, price: price(9.0), amount: 1, change: LevelChange::Change },
+        ]
+    );
+
+    let snapshot = book.snapshot(1);
+    assert_eq!(snapshot.sequence, 4);
+    assert_eq!(snapshot.bids, vec![proto::PriceLevel { price: 9.0, amount: 1 }]);
+    assert_eq!(snapshot.asks, vec![proto::PriceLevel { price: 11.0, amount: 4 }]);
+
+    // Direct cancels are folded into the next delta
+    book.cancel_order(3, 0);
+    let delta = book.add_order(&order(5, 20.0, 1, Action::Sell, OrderType::Limit)).unwrap().delta.unwrap();
+    assert_eq!(delta.sequence, 5);
+    assert_eq!(delta.levels.len(), 2);
+
+    // A pending stop leaves the levels as they were and produces no delta
+    assert_eq!(book.add_order(&stop(6, 0.0, 8.0, 1, Action::Sell, OrderType::Stop)).unwrap().delta, None);
+    assert_eq!(book.sequence(), 5);
+}
+
+#[test]
+fn refused_replace_leaves_book_and_feed_untouched() {
+    let mut book = book();
+    book.add_order(&order(2, 11.0, 5, Action::Sell, OrderType::Limit)).unwrap();
+    book.add_order(&post_only(order(1, 10.0, 5, Action::Buy, OrderType::Limit))).unwrap();
+    let (sequence, events) = (book.sequence(), book.event_sequence);
+
+    assert_eq!(
+        book.add_order(&order(1, 11.0, 5, Action::Buy, OrderType::Replace)).unwrap_err(),
+        RejectReason::PostOnlyWouldCross
+    );
+    assert_eq!(book.get_order(1).unwrap().price, price(10.0));
+    assert_eq!((book.sequence(), book.event_sequence), (sequence, events));
+
+    // The next order's feed only describes that order
+    let report = book.add_order(&order(3, 9.0, 1, Action::Buy, OrderType::Limit)).unwrap();
+    let delta = report.delta.unwrap();
+    assert_eq!(delta.sequence, sequence + 1);
+    assert_eq!(
+        delta.levels,
+        vec![LevelDelta { side: Action::Buy, price: price(9.0), amount: 1, change: LevelChange::New }]
+    );
+    assert_eq!(report.order_events.len(), 1);
+    assert_eq!((report.order_events[0].order_id, report.order_events[0].sequence), (3, events + 1));
+}
+
+#[test]
+fn order_events_are_sequenced() {
+    let mut book = book();
+    book.add_order(&order(1, 10.0, 5, Action::Sell, OrderType::Limit)).unwrap();
+    book.add_order(&order(2, 10.0, 5, Action::Sell, OrderType::Limit)).unwrap();
+    book.add_order(&order(1, 0.0, 2, Action::Sell, OrderType::Cancel)).unwrap();
+    let report = book.add_order(&order(3, 10.0, 4, Action::Buy, OrderType::Limit)).unwrap();
+    let events: Vec<_> = report
+        .order_events
+        .iter()
+        .map(|e| (e.sequence, e.kind, e.order_id, e.amount, e.queue_position, e.timestamp))
+        .collect();
+    assert_eq!(
+        events,
+        vec![
+            (4, OrderEventKind::Execute, 1, 3, 0, 3),
+            (5, OrderEventKind::Execute, 2, 1, 0, 3),
+        ]
+    );
+
+    let report = book.add_order(&order(2, 0.0, 0, Action::Sell, OrderType::Cancel)).unwrap();
+    assert_eq!(report.order_events[0].kind, OrderEventKind::Delete);
+    assert_eq!((report.order_events[0].sequence, report.order_events[0].amount), (6, 4));
+}
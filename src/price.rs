@@ -0,0 +1,148 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::{Add, Sub};
+
+/// Number of price units per 1.0, i.e. the finest tick any instrument can use
+pub const PRICE_SCALE: i64 = 1_000_000;
+const PRICE_DECIMALS: usize = 6;
+
+/// Fixed-point price stored as an integer number of 1e-6 units so that
+/// levels can be compared, hashed and ordered exactly.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Price(i64);
+
+impl Price {
+    pub const ZERO: Price = Price(0);
+
+    pub const fn from_units(units: i64) -> Self {
+        Price(units)
+    }
+
+    /// Converts a wire/display price, rounding to the nearest unit.
+    /// Non-finite input has no meaningful price and maps to `None`.
+    pub fn from_f64(price: f64) -> Option<Self> {
+        if !price.is_finite() {
+            return None;
+        }
+        Some(Price((price * PRICE_SCALE as f64).round() as i64))
+    }
+
+    pub const fn units(self) -> i64 {
+        self.0
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.0 as f64 / PRICE_SCALE as f64
+    }
+
+    pub fn abs(self) -> Self {
+        Price(self.0.abs())
+    }
+
+    pub fn is_multiple_of(self, tick_size: Price) -> bool {
+        tick_size.0 > 0 && self.0 % tick_size.0 == 0
+    }
+
+    /// Nearest multiple of `tick_size`, rounding half away from zero
+    pub fn round_to(self, tick_size: Price) -> Price {
+        if tick_size.0 <= 0 {
+            return self;
+        }
+        let half = tick_size.0 / 2;
+        let ticks = (self.0.abs() + half) / tick_size.0;
+        Price(self.0.signum() * ticks * tick_size.0)
+    }
+}
+
+impl Add for Price {
+    type Output = Price;
+
+    fn add(self, rhs: Price) -> Price {
+        Price(self.0 + rhs.0)
+    }
+}
+
+impl Sub for Price {
+    type Output = Price;
+
+    fn sub(self, rhs: Price) -> Price {
+        Price(self.0 - rhs.0)
+    }
+}
+
+impl fmt::Display for Price {
+    /// Formats exactly from the integer representation. A precision such as
+    /// `{:.2}` rounds half away from zero; without one trailing zeros are trimmed.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let decimals = f.precision().unwrap_or(PRICE_DECIMALS);
+        let mut units = self.0.unsigned_abs() as u128;
+        let mut scale = PRICE_SCALE as u128;
+        if decimals < PRICE_DECIMALS {
+            let step = 10u128.pow((PRICE_DECIMALS - decimals) as u32);
+            units = (units + step / 2) / step;
+            scale /= step;
+        }
+        let sign = if self.0 < 0 && units > 0 { "-" } else { "" };
+        let mut text = format!("{}{}", sign, units / scale);
+        if decimals > 0 {
+            let mut frac = format!("{:0width$}", units % scale, width = decimals.min(PRICE_DECIMALS));
+            if f.precision().is_none() {
+                frac.truncate(frac.trim_end_matches('0').len());
+            }
+            if !frac.is_empty() {
+                text.push('.');
+                text.push_str(&frac);
+                text.push_str(&"0".repeat(decimals.saturating_sub(PRICE_DECIMALS)));
+            }
+        }
+        match (f.width(), f.align()) {
+            (Some(width), Some(fmt::Alignment::Left)) => write!(f, "{:<width$}", text),
+            (Some(width), Some(fmt::Alignment::Center)) => write!(f, "{:^width$}", text),
+            (Some(width), _) => write!(f, "{:>width$}", text),
+            (None, _) => f.write_str(&text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(value: f64) -> Price {
+        Price::from_f64(value).unwrap()
+    }
+
+    #[test]
+    fn from_f64_rounds_to_the_nearest_unit() {
+        assert_eq!(price(0.1) + price(0.2), price(0.3));
+        assert_eq!(price(10.0000004).units(), 10_000_000);
+        assert_eq!(price(-1.0000006).units(), -1_000_001);
+        assert!(Price::from_f64(f64::NAN).is_none());
+        assert!(Price::from_f64(f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn display_rounds_half_away_from_zero() {
+        assert_eq!(format!("{:.2}", price(10.125)), "10.13");
+        assert_eq!(format!("{:.2}", price(-10.125)), "-10.13");
+        assert_eq!(format!("{:.0}", price(2.5)), "3");
+        assert_eq!(format!("{:.1}", price(-0.04)), "0.0");
+        assert_eq!(format!("{:.8}", price(1.5)), "1.50000000");
+    }
+
+    #[test]
+    fn display_trims_without_precision_and_pads_to_width() {
+        assert_eq!(price(10.1).to_string(), "10.1");
+        assert_eq!(price(10.0).to_string(), "10");
+        assert_eq!(price(0.000001).to_string(), "0.000001");
+        assert_eq!(format!("{:>7.2}", price(1.5)), "   1.50");
+        assert_eq!(format!("{:<6}", price(1.5)), "1.5   ");
+    }
+
+    #[test]
+    fn tick_multiples() {
+        assert!(price(10.05).is_multiple_of(price(0.01)));
+        assert!(!price(10.005).is_multiple_of(price(0.01)));
+        assert!(!price(10.0).is_multiple_of(Price::ZERO));
+    }
+}
@@ -0,0 +1,379 @@
+use crate::messaging::{proto, MarketDataConfig};
+use crate::orderbook::OrderBook;
+use crate::price::Price;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// When a changed book is worth sending to a subscriber, judged against the
+/// last update that subscriber was sent
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum PublishPolicy {
+    /// Any change of best bid or offer price or size
+    TopOfBook,
+    /// Any change of price or size within the top `levels` of either side
+    Depth { levels: usize },
+    /// A best price change, or a best size change of more than `percent`
+    SizeChange { percent: f64 },
+    /// A best price move of more than `threshold`; sizes are ignored
+    PriceMove { threshold: Price },
+}
+
+/// One consumer of book updates and how it wants them delivered
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Subscriber {
+    pub name: String,
+    /// Subject template, see `MarketDataConfig::subject`
+    pub subject: String,
+    /// Levels per side in the published `BookUpdate`
+    pub depth: usize,
+    pub policy: PublishPolicy,
+    /// Publish at most once per window; changes inside the window are
+    /// conflated into one update sent when it closes
+    #[serde(default)]
+    pub conflation: Option<Duration>,
+}
+
+/// Subscribers read from `PUBLISHING_CONFIG`. Defaults to those of
+/// `for_market_data`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct PublishingConfig {
+    pub subscribers: Vec<Subscriber>,
+}
+
+impl Default for PublishingConfig {
+    fn default() -> Self {
+        Self::for_market_data(&MarketDataConfig::default())
+    }
+}
+
+impl PublishingConfig {
+    /// The "top" and "book" subscribers on the top of book and book subjects
+    /// of `config`, the book one `book_depth` levels deep
+    pub fn for_market_data(config: &MarketDataConfig) -> Self {
+        Self {
+            subscribers: vec![
+                Subscriber {
+                    name: "top".to_string(),
+                    subject: config.subjects.top_of_book.clone(),
+                    depth: 1,
+                    policy: PublishPolicy::TopOfBook,
+                    conflation: None,
+                },
+                Subscriber {
+                    name: "book".to_string(),
+                    subject: config.subjects.book.clone(),
+                    depth: config.book_depth,
+                    policy: PublishPolicy::Depth {
+                        levels: config.book_depth,
+                    },
+                    conflation: None,
+                },
+            ],
+        }
+    }
+}
+
+impl PublishPolicy {
+    /// Levels per side the policy looks at
+    fn levels(&self) -> usize {
+        match self {
+            PublishPolicy::Depth { levels } => *levels,
+            _ => 1,
+        }
+    }
+
+    /// Whether `new` differs enough from `last`. Nothing sent yet always counts.
+    pub fn should_publish(&self, new: &proto::BookUpdate, last: Option<&proto::BookUpdate>) -> bool {
+        let Some(last) = last else {
+            return true;
+        };
+        let sides = [(&new.bids, &last.bids), (&new.asks, &last.asks)];
+        match self {
+            PublishPolicy::TopOfBook => sides.iter().any(|(n, l)| n.first() != l.first()),
+            PublishPolicy::Depth { levels } => sides.iter().any(|(n, l)| {
+                n.iter().take(*levels).ne(l.iter().take(*levels))
+            }),
+            PublishPolicy::SizeChange { percent } => sides.iter().any(|(n, l)| match (n.first(), l.first()) {
+                (Some(n), Some(l)) => {
+                    n.price != l.price || (n.amount - l.amount).abs() as f64 * 100.0 > l.amount as f64 * percent
+                }
+                (n, l) => n != l,
+            }),
+            // Compared in fixed-point units so 0.04 - 0.03 is exactly 0.01
+            PublishPolicy::PriceMove { threshold } => sides.iter().any(|(n, l)| {
+                let price = |level: Option<&proto::PriceLevel>| level.and_then(|l| Price::from_f64(l.price));
+                match (price(n.first()), price(l.first())) {
+                    (Some(n), Some(l)) => (n - l).abs() > *threshold,
+                    (n, l) => n.is_some() != l.is_some(),
+                }
+            }),
+        }
+    }
+}
+
+/// Latest snapshot of every symbol, deep enough for every subscriber
+#[derive(Debug, Clone, Default)]
+pub struct LastValueCache {
+    books: HashMap<String, proto::BookUpdate>,
+}
+
+impl LastValueCache {
+    pub fn get(&self, symbol: &str) -> Option<&proto::BookUpdate> {
+        self.books.get(symbol)
+    }
+
+    pub fn len(&self) -> usize {
+        self.books.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.books.is_empty()
+    }
+}
+
+/// A book update due to one subscriber
+#[derive(Debug, Clone, PartialEq)]
+pub struct Publication {
+    pub subscriber: String,
+    /// Subject template of the subscriber
+    pub subject: String,
+    pub update: proto::BookUpdate,
+}
+
+/// What a subscriber was last sent for one symbol
+#[derive(Debug, Clone, Default)]
+struct Sent {
+    update: Option<proto::BookUpdate>,
+    at: u128,
+    /// A change arrived inside the conflation window and is not sent yet
+    pending: bool,
+}
+
+/// Decides which subscribers get which book updates
+#[derive(Debug, Clone)]
+pub struct Publisher {
+    subscribers: Vec<Subscriber>,
+    cache: LastValueCache,
+    /// Per subscriber, by symbol
+    sent: Vec<HashMap<String, Sent>>,
+    pending: usize,
+    depth: usize,
+}
+
+impl Publisher {
+    pub fn new(config: PublishingConfig) -> Self {
+        let depth = config
+            .subscribers
+            .iter()
+            .map(|s| s.depth.max(s.policy.levels()))
+            .max()
+            .unwrap_or(0);
+        Self {
+            sent: vec![HashMap::new(); config.subscribers.len()],
+            subscribers: config.subscribers,
+            cache: LastValueCache::default(),
+            pending: 0,
+            depth,
+        }
+    }
+
+    pub fn subscribers(&self) -> &[Subscriber] {
+        &self.subscribers
+    }
+
+    pub fn cache(&self) -> &LastValueCache {
+        &self.cache
+    }
+
+    /// Caches the book's latest levels and returns the updates due now. `now`
+    /// is the local clock in nanoseconds, used for conflation windows.
+    pub fn on_book_change(&mut self, book: &OrderBook, now: u128) -> Vec<Publication> {
+        let snapshot = book.snapshot(self.depth);
+        self.cache.books.insert(book.symbol.clone(), snapshot);
+        (0..self.subscribers.len())
+            .filter_map(|i| self.evaluate(i, &book.symbol, now))
+            .collect()
+    }
+
+    /// Whether any conflated update is waiting for its window to close
+    pub fn has_pending(&self) -> bool {
+        self.pending > 0
+    }
+
+    /// Returns conflated updates whose window has closed
+    pub fn poll(&mut self, now: u128) -> Vec<Publication> {
+        if self.pending == 0 {
+            return Vec::new();
+        }
+        let mut due = Vec::new();
+        for i in 0..self.subscribers.len() {
+            let symbols: Vec<String> = self.sent[i]
+                .iter()
+                .filter(|(_, sent)| sent.pending)
+                .map(|(symbol, _)| symbol.clone())
+                .collect();
+            due.extend(symbols.iter().filter_map(|symbol| self.evaluate(i, symbol, now)));
+        }
+        due
+    }
+
+    /// Applies subscriber `i`'s policy and conflation window to the cached
+    /// book of `symbol`
+    fn evaluate(&mut self, i: usize, symbol: &str, now: u128) -> Option<Publication> {
+        let subscriber = &self.subscribers[i];
+        let latest = self.cache.books.get(symbol)?;
+        let sent = self.sent[i].entry(symbol.to_string()).or_default();
+        let view = truncate(latest, subscriber.depth.max(subscriber.policy.levels()));
+        if !subscriber.policy.should_publish(&view, sent.update.as_ref()) {
+            if sent.pending {
+                sent.pending = false;
+                self.pending -= 1;
+            }
+            return None;
+        }
+        let window_open = sent.update.is_some()
+            && subscriber.conflation.is_some_and(|window| now < sent.at + window.as_nanos());
+        if window_open {
+            if !sent.pending {
+                sent.pending = true;
+                self.pending += 1;
+            }
+            return None;
+        }
+        if sent.pending {
+            sent.pending = false;
+            self.pending -= 1;
+        }
+        sent.update = Some(view);
+        sent.at = now;
+        Some(Publication {
+            subscriber: subscriber.name.clone(),
+            subject: subscriber.subject.clone(),
+            update: truncate(latest, subscriber.depth),
+        })
+    }
+}
+
+/// Copy of `update` with at most `depth` levels per side
+fn truncate(update: &proto::BookUpdate, depth: usize) -> proto::BookUpdate {
+    proto::BookUpdate {
+        bids: update.bids.iter().take(depth).cloned().collect(),
+        asks: update.asks.iter().take(depth).cloned().collect(),
+        ..update.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::tests::order;
+    use crate::orderbook::{Action, OrderType};
+
+    fn subscriber(name: &str, policy: PublishPolicy, conflation: Option<Duration>) -> Subscriber {
+        Subscriber {
+            name: name.to_string(),
+            subject: format!("{}.{{symbol}}", name),
+            depth: 2,
+            policy,
+            conflation,
+        }
+    }
+
+    fn names(publications: Vec<Publication>) -> Vec<String> {
+        publications.into_iter().map(|p| p.subscriber).collect()
+    }
+
+    fn top(bid: f64, amount: i32) -> proto::BookUpdate {
+        proto::BookUpdate {
+            bids: vec![proto::PriceLevel { price: bid, amount }],
+            ..proto::BookUpdate::default()
+        }
+    }
+
+    #[test]
+    fn default_subscribers_follow_the_market_data_config() {
+        let config: MarketDataConfig =
+            serde_json::from_str(r#"{"book_depth": 5, "subjects": {"top_of_book": "bbo.{symbol}"}}"#).unwrap();
+        let subscribers = PublishingConfig::for_market_data(&config).subscribers;
+        assert_eq!(subscribers[0].subject, "bbo.{symbol}");
+        assert_eq!((subscribers[0].depth, &subscribers[0].policy), (1, &PublishPolicy::TopOfBook));
+        assert_eq!(subscribers[1].subject, "book.{venue}.{symbol}");
+        assert_eq!((subscribers[1].depth, &subscribers[1].policy), (5, &PublishPolicy::Depth { levels: 5 }));
+
+        let defaults = PublishingConfig::default();
+        assert_eq!(defaults, PublishingConfig::for_market_data(&MarketDataConfig::default()));
+        assert_eq!(defaults.subscribers[1].depth, crate::messaging::DEFAULT_BOOK_DEPTH);
+    }
+
+    #[test]
+    fn policies_compare_against_the_last_update_sent() {
+        let mut publisher = Publisher::new(PublishingConfig {
+            subscribers: vec![
+                subscriber("top", PublishPolicy::TopOfBook, None),
+                subscriber("size", PublishPolicy::SizeChange { percent: 50.0 }, None),
+                subscriber("depth", PublishPolicy::Depth { levels: 2 }, None),
+            ],
+        });
+        let mut book = OrderBook::new("TSLA".to_string());
+        book.add_order(&order(1, 10.0, 10, Action::Buy, OrderType::Limit)).unwrap();
+        assert_eq!(names(publisher.on_book_change(&book, 0)), ["top", "size", "depth"]);
+
+        // 10 -> 12 is a 20% size change
+        book.add_order(&order(2, 10.0, 2, Action::Buy, OrderType::Limit)).unwrap();
+        assert_eq!(names(publisher.on_book_change(&book, 1)), ["top", "depth"]);
+
+        book.add_order(&order(3, 9.0, 2, Action::Buy, OrderType::Limit)).unwrap();
+        assert_eq!(names(publisher.on_book_change(&book, 2)), ["depth"]);
+
+        // 10 -> 32 against the last 10 the size subscriber saw
+        book.add_order(&order(4, 10.0, 20, Action::Buy, OrderType::Limit)).unwrap();
+        let due = publisher.on_book_change(&book, 3);
+        assert_eq!(names(due.clone()), ["top", "size", "depth"]);
+        assert_eq!(due[0].subject, "top.{symbol}");
+        assert_eq!(due[0].update.bids.len(), 2);
+        assert_eq!(publisher.cache().get("TSLA").unwrap().bids[0].amount, 32);
+    }
+
+    #[test]
+    fn price_move_is_measured_in_price_units() {
+        let policy = PublishPolicy::PriceMove { threshold: Price::from_f64(0.01).unwrap() };
+        assert!(!policy.should_publish(&top(0.04, 1), Some(&top(0.03, 1))));
+        assert!(!policy.should_publish(&top(300.01, 1), Some(&top(300.00, 1))));
+        assert!(policy.should_publish(&top(300.02, 1), Some(&top(300.00, 1))));
+        assert!(!policy.should_publish(&top(300.00, 9), Some(&top(300.00, 1))));
+        assert!(policy.should_publish(&proto::BookUpdate::default(), Some(&top(300.00, 1))));
+    }
+
+    #[test]
+    fn conflation_sends_the_latest_book_when_the_window_closes() {
+        let window = Some(Duration::from_nanos(100));
+        let mut publisher = Publisher::new(PublishingConfig {
+            subscribers: vec![subscriber("slow", PublishPolicy::Depth { levels: 2 }, window)],
+        });
+        let mut book = OrderBook::new("TSLA".to_string());
+        book.add_order(&order(1, 10.0, 1, Action::Buy, OrderType::Limit)).unwrap();
+        assert_eq!(publisher.on_book_change(&book, 0).len(), 1);
+
+        book.add_order(&order(2, 9.0, 1, Action::Buy, OrderType::Limit)).unwrap();
+        assert!(publisher.on_book_change(&book, 10).is_empty());
+        book.add_order(&order(3, 8.0, 1, Action::Buy, OrderType::Limit)).unwrap();
+        assert!(publisher.on_book_change(&book, 20).is_empty());
+        assert!(publisher.has_pending());
+        assert!(publisher.poll(99).is_empty());
+
+        let due = publisher.poll(100);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].update.bids.len(), 2);
+        assert!(!publisher.has_pending());
+
+        // A change undone inside the window is never sent
+        book.add_order(&order(4, 10.0, 1, Action::Buy, OrderType::Limit)).unwrap();
+        assert!(publisher.on_book_change(&book, 150).is_empty());
+        book.cancel_order(4, 0);
+        assert!(publisher.on_book_change(&book, 160).is_empty());
+        assert!(!publisher.has_pending());
+        assert!(publisher.poll(300).is_empty());
+    }
+}
@@ -0,0 +1,313 @@
+use crate::instrument::Instrument;
+use crate::orderbook::{Order, OrderType};
+use crate::price::Price;
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fmt;
+use std::time::Duration;
+
+/// A single check applied to incoming orders before they reach a book
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Rule {
+    /// Limit and stop prices are whole multiples of the tick size
+    TickSize,
+    /// Quantity is a whole multiple of the lot size
+    LotSize,
+    MinQuantity,
+    MaxQuantity,
+    /// Limit price is within the band around the reference price
+    PriceBand,
+    /// Instrument is on the whitelist
+    Instrument,
+    /// Timestamp is neither too far in the future nor too old
+    Timestamp,
+    /// Id was not already used by a recent new order
+    DuplicateId,
+    /// Timestamp falls inside the instrument's trading hours
+    TradingHours,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Rule::TickSize => "tick size",
+            Rule::LotSize => "lot size",
+            Rule::MinQuantity => "min quantity",
+            Rule::MaxQuantity => "max quantity",
+            Rule::PriceBand => "price band",
+            Rule::Instrument => "instrument",
+            Rule::Timestamp => "timestamp",
+            Rule::DuplicateId => "duplicate id",
+            Rule::TradingHours => "trading hours",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Which rules to apply and their limits. Rules left as `None` are skipped.
+/// Tick and lot sizes from instrument reference data take precedence over
+/// the ones configured here.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ValidationConfig {
+    pub tick_size: Option<Price>,
+    pub lot_size: Option<i32>,
+    pub min_quantity: Option<i32>,
+    pub max_quantity: Option<i32>,
+    /// Largest distance of a limit price from the reference price, in basis points
+    pub price_band_bps: Option<i64>,
+    pub instruments: Option<HashSet<String>>,
+    /// How far an order timestamp may be ahead of the local clock
+    pub max_clock_skew: Option<Duration>,
+    /// How far an order timestamp may be behind the local clock
+    pub max_age: Option<Duration>,
+    /// Number of most recent new-order ids remembered for duplicate detection
+    pub duplicate_id_window: Option<usize>,
+    /// Refuse orders stamped outside the instrument's trading hours
+    pub check_trading_hours: bool,
+}
+
+/// Applies a `ValidationConfig` to incoming orders and counts violations per rule
+#[derive(Debug, Clone)]
+pub struct Validator {
+    pub config: ValidationConfig,
+    recent_ids: HashSet<u128>,
+    recent_id_order: VecDeque<u128>,
+    checked: u64,
+    violations: BTreeMap<Rule, u64>,
+}
+
+impl Validator {
+    pub fn new(config: ValidationConfig) -> Self {
+        Self {
+            config,
+            recent_ids: HashSet::new(),
+            recent_id_order: VecDeque::new(),
+            checked: 0,
+            violations: BTreeMap::new(),
+        }
+    }
+
+    /// Checks `order` against every configured rule and returns the first one
+    /// it breaks. `instrument` is the order's reference data, if known.
+    /// `reference` is the price the band is measured from, usually the book's
+    /// last trade; without one the band is not applied. `now` is the local
+    /// clock in nanoseconds since the epoch.
+    pub fn validate(
+        &mut self,
+        order: &Order,
+        instrument: Option<&Instrument>,
+        reference: Option<Price>,
+        now: u128,
+    ) -> Result<(), Rule> {
+        self.checked += 1;
+        let result = self.check(order, instrument, reference, now);
+        match result {
+            Ok(()) => self.remember_id(order),
+            Err(rule) => *self.violations.entry(rule).or_insert(0) += 1,
+        }
+        result
+    }
+
+    fn check(
+        &self,
+        order: &Order,
+        instrument: Option<&Instrument>,
+        reference: Option<Price>,
+        now: u128,
+    ) -> Result<(), Rule> {
+        let config = &self.config;
+        if config.instruments.as_ref().is_some_and(|set| !set.contains(&order.instrument)) {
+            return Err(Rule::Instrument);
+        }
+        if config.max_clock_skew.is_some_and(|skew| order.timestamp > now + skew.as_nanos())
+            || config.max_age.is_some_and(|age| order.timestamp + age.as_nanos() < now)
+        {
+            return Err(Rule::Timestamp);
+        }
+        if let Some(hours) = instrument.and_then(|i| i.trading_hours).filter(|_| config.check_trading_hours) {
+            let time = DateTime::from_timestamp_nanos(order.timestamp as i64).time();
+            if !hours.contains(time) {
+                return Err(Rule::TradingHours);
+            }
+        }
+        if order.order_type == OrderType::Cancel {
+            return Ok(());
+        }
+
+        let is_new = order.order_type != OrderType::Replace;
+        if is_new && config.duplicate_id_window.is_some() && self.recent_ids.contains(&order.id) {
+            return Err(Rule::DuplicateId);
+        }
+
+        let limit_priced = matches!(order.order_type, OrderType::Limit | OrderType::Replace | OrderType::StopLimit);
+        let stop_priced = matches!(order.order_type, OrderType::Stop | OrderType::StopLimit);
+        if let Some(tick_size) = instrument.map(|i| i.tick_size).or(config.tick_size) {
+            if (limit_priced && !order.price.is_multiple_of(tick_size))
+                || (stop_priced && !order.stop_price.is_multiple_of(tick_size))
+            {
+                return Err(Rule::TickSize);
+            }
+        }
+
+        // A replace to zero quantity is a cancel
+        if order.order_type == OrderType::Replace && order.amount == 0 {
+            return Ok(());
+        }
+        let lot_size = instrument.map(|i| i.lot_size).or(config.lot_size);
+        if lot_size.is_some_and(|lot| lot > 0 && order.amount % lot != 0) {
+            return Err(Rule::LotSize);
+        }
+        if config.min_quantity.is_some_and(|min| order.amount < min) {
+            return Err(Rule::MinQuantity);
+        }
+        if config.max_quantity.is_some_and(|max| order.amount > max) {
+            return Err(Rule::MaxQuantity);
+        }
+
+        if let (Some(bps), Some(reference), true) = (config.price_band_bps, reference, limit_priced) {
+            let distance = (order.price - reference).abs().units() as i128;
+            if distance * 10_000 > reference.units() as i128 * bps as i128 {
+                return Err(Rule::PriceBand);
+            }
+        }
+        Ok(())
+    }
+
+    fn remember_id(&mut self, order: &Order) {
+        let Some(window) = self.config.duplicate_id_window else {
+            return;
+        };
+        if matches!(order.order_type, OrderType::Cancel | OrderType::Replace) {
+            return;
+        }
+        if self.recent_ids.insert(order.id) {
+            self.recent_id_order.push_back(order.id);
+        }
+        while self.recent_id_order.len() > window {
+            if let Some(old) = self.recent_id_order.pop_front() {
+                self.recent_ids.remove(&old);
+            }
+        }
+    }
+
+    /// Number of orders passed to `validate`
+    pub fn checked(&self) -> u64 {
+        self.checked
+    }
+
+    /// Violation count per rule; rules never broken are absent
+    pub fn violations(&self) -> &BTreeMap<Rule, u64> {
+        &self.violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::tests::{order, price};
+    use crate::instrument::TradingHours;
+    use crate::orderbook::Action;
+    use chrono::NaiveTime;
+
+    const SECOND: u128 = 1_000_000_000;
+
+    fn limit(id: u128, value: f64, amount: i32) -> Order {
+        let mut order = order(id, value, amount, Action::Buy, OrderType::Limit);
+        order.timestamp = 100 * SECOND;
+        order
+    }
+
+    #[test]
+    fn tick_lot_and_quantity_limits() {
+        let mut validator = Validator::new(ValidationConfig {
+            tick_size: Some(price(0.01)),
+            lot_size: Some(10),
+            min_quantity: Some(10),
+            max_quantity: Some(100),
+            ..ValidationConfig::default()
+        });
+        let now = 100 * SECOND;
+        assert_eq!(validator.validate(&limit(1, 10.0, 20), None, None, now), Ok(()));
+        assert_eq!(validator.validate(&limit(2, 10.005, 20), None, None, now), Err(Rule::TickSize));
+        assert_eq!(validator.validate(&limit(3, 10.0, 15), None, None, now), Err(Rule::LotSize));
+        assert_eq!(validator.validate(&limit(4, 10.0, 110), None, None, now), Err(Rule::MaxQuantity));
+        assert_eq!(validator.checked(), 4);
+        assert_eq!(validator.violations().values().sum::<u64>(), 3);
+    }
+
+    #[test]
+    fn price_band_needs_a_reference() {
+        let mut validator = Validator::new(ValidationConfig {
+            price_band_bps: Some(100),
+            ..ValidationConfig::default()
+        });
+        let now = 100 * SECOND;
+        assert_eq!(validator.validate(&limit(1, 102.0, 1), None, None, now), Ok(()));
+        assert_eq!(validator.validate(&limit(2, 102.0, 1), None, Some(price(100.0)), now), Err(Rule::PriceBand));
+        assert_eq!(validator.validate(&limit(3, 101.0, 1), None, Some(price(100.0)), now), Ok(()));
+    }
+
+    #[test]
+    fn timestamps_and_duplicate_ids() {
+        let mut validator = Validator::new(ValidationConfig {
+            max_clock_skew: Some(Duration::from_secs(1)),
+            max_age: Some(Duration::from_secs(5)),
+            duplicate_id_window: Some(2),
+            ..ValidationConfig::default()
+        });
+        assert_eq!(validator.validate(&limit(1, 10.0, 1), None, None, 98 * SECOND), Err(Rule::Timestamp));
+        assert_eq!(validator.validate(&limit(1, 10.0, 1), None, None, 106 * SECOND), Err(Rule::Timestamp));
+
+        let now = 100 * SECOND;
+        assert_eq!(validator.validate(&limit(1, 10.0, 1), None, None, now), Ok(()));
+        assert_eq!(validator.validate(&limit(2, 10.0, 1), None, None, now), Ok(()));
+        assert_eq!(validator.validate(&limit(1, 10.0, 1), None, None, now), Err(Rule::DuplicateId));
+        // Cancels reuse ids
+        let mut cancel = limit(1, 0.0, 0);
+        cancel.order_type = OrderType::Cancel;
+        assert_eq!(validator.validate(&cancel, None, None, now), Ok(()));
+        // Id 1 leaves the window once two newer ids were accepted
+        assert_eq!(validator.validate(&limit(3, 10.0, 1), None, None, now), Ok(()));
+        assert_eq!(validator.validate(&limit(1, 10.0, 1), None, None, now), Ok(()));
+        assert_eq!(validator.violations()[&Rule::DuplicateId], 1);
+        assert_eq!(validator.violations()[&Rule::Timestamp], 2);
+    }
+
+    #[test]
+    fn instrument_data_overrides_config_and_sets_hours() {
+        let mut validator = Validator::new(ValidationConfig {
+            tick_size: Some(price(0.01)),
+            check_trading_hours: true,
+            ..ValidationConfig::default()
+        });
+        let instrument = Instrument {
+            symbol: "TSLA".to_string(),
+            tick_size: price(0.05),
+            lot_size: 10,
+            price_decimals: 2,
+            trading_hours: Some(TradingHours {
+                open: NaiveTime::from_hms_opt(13, 30, 0).unwrap(),
+                close: NaiveTime::from_hms_opt(20, 0, 0).unwrap(),
+            }),
+            reference_price: None,
+            price_collar: None,
+            dynamic_collar_bps: None,
+            volatility_guard: None,
+        };
+        // 100 seconds after midnight is outside the session
+        let now = 100 * SECOND;
+        assert_eq!(validator.validate(&limit(1, 10.0, 10), Some(&instrument), None, now), Err(Rule::TradingHours));
+
+        let in_session = 14 * 3600 * SECOND;
+        let mut at = |id, value, amount| {
+            let mut order = limit(id, value, amount);
+            order.timestamp = in_session;
+            validator.validate(&order, Some(&instrument), None, in_session)
+        };
+        assert_eq!(at(2, 10.05, 10), Ok(()));
+        assert_eq!(at(3, 10.01, 10), Err(Rule::TickSize));
+        assert_eq!(at(4, 10.05, 5), Err(Rule::LotSize));
+    }
+}